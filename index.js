@@ -8,6 +8,8 @@ async function loadWasm() {
   await wbg.start();
   let context = document.getElementById('canvas').getContext('2d');
   window.addEventListener("keydown", async (event) => await wbg.keydownhandler(event, context));
+  window.addEventListener("wheel", (event) => wbg.wheelhandler(event, context));
+  window.addEventListener("resize", () => wbg.resizehandler(context));
 }
 
 loadWasm();
\ No newline at end of file
@@ -0,0 +1,385 @@
+use crate::env::ENV;
+use crate::vfs::complete_path;
+use std::collections::{HashMap, HashSet};
+
+// What a positional argument or flag value has to look like.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum ArgKind {
+    Text,
+    Number,
+    Integer,
+    // the name of a registered command
+    Command,
+    // the name of a shell variable, set or not
+    Variable,
+    // a file or directory in the virtual filesystem
+    Path,
+}
+
+impl ArgKind {
+    fn parse(&self, text: &str, commands: &[String]) -> Result<Value, String> {
+        match self {
+            ArgKind::Text => Ok(Value::Text(text.to_string())),
+            ArgKind::Variable | ArgKind::Path => Ok(Value::Text(text.to_string())),
+            ArgKind::Number => text.parse().map(Value::Number).map_err(|_| format!("'{}' is not a number", text)),
+            ArgKind::Integer => text.parse().map(Value::Integer).map_err(|_| format!("'{}' is not a whole number", text)),
+            ArgKind::Command => match commands.iter().any(|name| name.eq_ignore_ascii_case(text)) {
+                true => Ok(Value::Text(text.to_lowercase())),
+                false => Err(format!("'{}' is not a command", text)),
+            },
+        }
+    }
+
+    fn candidates(&self, word: &str, commands: &[String]) -> Vec<String> {
+        match self {
+            ArgKind::Command => commands.to_vec(),
+            ArgKind::Variable => ENV.lock().unwrap().names(),
+            ArgKind::Path => complete_path(word),
+            _ => Vec::new(),
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub enum Value {
+    Text(String),
+    Number(f64),
+    Integer(i64),
+}
+
+impl Value {
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Value::Text(text) => Some(text),
+            _ => None,
+        }
+    }
+
+    pub fn as_number(&self) -> Option<f64> {
+        match self {
+            Value::Number(number) => Some(*number),
+            Value::Integer(integer) => Some(*integer as f64),
+            Value::Text(_) => None,
+        }
+    }
+
+    pub fn as_integer(&self) -> Option<i64> {
+        match self {
+            Value::Integer(integer) => Some(*integer),
+            _ => None,
+        }
+    }
+}
+
+pub struct Arg {
+    name: &'static str,
+    kind: ArgKind,
+    optional: bool,
+    variadic: bool,
+}
+
+impl Arg {
+    pub fn new(name: &'static str, kind: ArgKind) -> Arg {
+        Arg { name, kind, optional: false, variadic: false }
+    }
+
+    pub fn optional(mut self) -> Arg {
+        self.optional = true;
+        self
+    }
+
+    // takes every remaining argument; at least one unless also optional
+    pub fn variadic(mut self) -> Arg {
+        self.variadic = true;
+        self
+    }
+
+    fn usage(&self) -> String {
+        let dots = if self.variadic { "..." } else { "" };
+        match self.optional {
+            true => format!("[{}]{}", self.name, dots),
+            false => format!("<{}>{}", self.name, dots),
+        }
+    }
+}
+
+pub struct Flag {
+    long: &'static str,
+    short: Option<char>,
+    help: &'static str,
+}
+
+impl Flag {
+    pub fn new(long: &'static str, help: &'static str) -> Flag {
+        Flag { long, short: None, help }
+    }
+
+    pub fn short(mut self, short: char) -> Flag {
+        self.short = Some(short);
+        self
+    }
+
+    fn usage(&self) -> String {
+        match self.short {
+            Some(short) => format!("[-{}|--{}]", short, self.long),
+            None => format!("[--{}]", self.long),
+        }
+    }
+}
+
+// Everything a command accepts: subcommands, positional arguments and flags.
+// Input is checked against it before the command runs, and the same
+// declaration produces the usage text and the Tab completions.
+#[derive(Default)]
+pub struct Spec {
+    subcommands: Vec<(&'static str, &'static str, Spec)>,
+    args: Vec<Arg>,
+    flags: Vec<Flag>,
+}
+
+// The validated arguments handed to a command.
+#[derive(Default, Debug)]
+pub struct Args {
+    subcommand: Option<&'static str>,
+    values: HashMap<&'static str, Vec<Value>>,
+    flags: HashSet<&'static str>,
+}
+
+impl Spec {
+    pub fn new() -> Spec {
+        Spec::default()
+    }
+
+    pub fn subcommand(mut self, name: &'static str, summary: &'static str, spec: Spec) -> Spec {
+        self.subcommands.push((name, summary, spec));
+        self
+    }
+
+    pub fn arg(mut self, arg: Arg) -> Spec {
+        self.args.push(arg);
+        self
+    }
+
+    pub fn flag(mut self, flag: Flag) -> Spec {
+        self.flags.push(flag);
+        self
+    }
+
+    // One line per form of the command, e.g. "calc add <number>...".
+    pub fn usage(&self, name: &str) -> String {
+        let mut lines = Vec::new();
+        for (sub, _, spec) in &self.subcommands {
+            lines.push(spec.usage(&format!("{} {}", name, sub)));
+        }
+        if self.subcommands.is_empty() || !self.args.is_empty() {
+            let mut parts = vec![name.to_string()];
+            parts.extend(self.flags.iter().map(Flag::usage));
+            parts.extend(self.args.iter().map(Arg::usage));
+            lines.push(parts.join(" "));
+        }
+        lines.join("\n")
+    }
+
+    // Longer help: the usage followed by what each subcommand and flag does.
+    pub fn help(&self, name: &str) -> String {
+        let mut text = format!("Usage: {}", self.usage(name).replace('\n', "\n       "));
+        for (sub, summary, _) in &self.subcommands {
+            text += &format!("\n  {:<16}{}", sub, summary);
+        }
+        for flag in &self.flags {
+            let short = flag.short.map(|short| format!("-{}, ", short)).unwrap_or_default();
+            text += &format!("\n  {:<16}{}", format!("{}--{}", short, flag.long), flag.help);
+        }
+        text
+    }
+
+    // Checks `input` against the spec. `commands` are the registered command
+    // names, for arguments of kind Command.
+    pub fn parse(&self, input: Vec<String>, commands: &[String]) -> Result<Args, String> {
+        let mut parsed = Args::default();
+        self.parse_into(&mut parsed, input, commands)?;
+        Ok(parsed)
+    }
+
+    fn parse_into(&self, parsed: &mut Args, input: Vec<String>, commands: &[String]) -> Result<(), String> {
+        if !self.subcommands.is_empty() {
+            let names: Vec<&str> = self.subcommands.iter().map(|(name, _, _)| *name).collect();
+            match input.first() {
+                Some(first) => {
+                    if let Some((name, _, spec)) = self.subcommands.iter().find(|(name, _, _)| name.eq_ignore_ascii_case(first)) {
+                        parsed.subcommand = Some(name);
+                        return spec.parse_into(parsed, input[1..].to_vec(), commands);
+                    }
+                    if self.args.is_empty() {
+                        return Err(format!("unknown subcommand '{}' (expected one of {})", first, names.join(", ")));
+                    }
+                }
+                None if self.args.iter().all(|arg| arg.optional) => {}
+                None => return Err(format!("missing subcommand (expected one of {})", names.join(", "))),
+            }
+        }
+
+        let mut positional = Vec::new();
+        let mut flags_done = false;
+        for word in input {
+            if flags_done || self.flags.is_empty() || !word.starts_with('-') || word == "-" || is_number(&word) {
+                positional.push(word);
+                continue;
+            }
+            if word == "--" {
+                flags_done = true;
+                continue;
+            }
+            // short flags can be grouped, as in `ls -la`
+            let flags: Option<Vec<&Flag>> = match word.strip_prefix("--") {
+                Some(long) => self.flags.iter().find(|flag| flag.long == long).map(|flag| vec![flag]),
+                None => word[1..].chars().map(|short| self.flags.iter().find(|flag| flag.short == Some(short))).collect(),
+            };
+            let Some(flags) = flags else {
+                return Err(format!("unknown flag '{}'", word));
+            };
+            parsed.flags.extend(flags.iter().map(|flag| flag.long));
+        }
+
+        let mut positional = positional.into_iter();
+        for (i, arg) in self.args.iter().enumerate() {
+            // leave enough words for the required arguments after this one,
+            // so `cp <source>... <dest>` works
+            let remaining = positional.len();
+            let reserved = self.args[i + 1..].iter().filter(|arg| !arg.optional).count();
+            let spare = remaining.saturating_sub(reserved);
+            let take = match (arg.variadic, arg.optional) {
+                (true, true) => spare,
+                (true, false) => spare.max(remaining.min(1)),
+                (false, true) => spare.min(1),
+                (false, false) => remaining.min(1),
+            };
+            let mut values = Vec::new();
+            for word in positional.by_ref().take(take) {
+                values.push(arg.kind.parse(&word, commands).map_err(|err| format!("{} for <{}>", err, arg.name))?);
+            }
+            if values.is_empty() && !arg.optional {
+                return Err(format!("missing argument <{}>", arg.name));
+            }
+            parsed.values.insert(arg.name, values);
+        }
+        match positional.next() {
+            Some(extra) => Err(format!("unexpected argument '{}'", extra)),
+            None => Ok(()),
+        }
+    }
+
+    // What `word` could become after the already typed `args`: subcommand
+    // names, flag names and the values the next positional argument allows.
+    pub fn complete(&self, args: &[String], word: &str, commands: &[String]) -> Vec<String> {
+        if let Some(first) = args.first() {
+            if let Some((_, _, spec)) = self.subcommands.iter().find(|(name, _, _)| name.eq_ignore_ascii_case(first)) {
+                return spec.complete(&args[1..], word, commands);
+            }
+        }
+
+        let mut candidates: Vec<String> = self.flags.iter().map(|flag| format!("--{}", flag.long)).collect();
+        if args.is_empty() {
+            candidates.extend(self.subcommands.iter().map(|(name, _, _)| name.to_string()));
+        }
+        let position = args.iter().filter(|arg| !arg.starts_with('-') || is_number(arg)).count();
+        let next = self.args.get(position).or(self.args.last().filter(|arg| arg.variadic));
+        if let Some(arg) = next {
+            candidates.extend(arg.kind.candidates(word, commands));
+        }
+        candidates
+    }
+}
+
+fn is_number(word: &str) -> bool {
+    word.parse::<f64>().is_ok()
+}
+
+impl Args {
+    pub fn subcommand(&self) -> Option<&str> {
+        self.subcommand
+    }
+
+    pub fn values(&self, name: &str) -> &[Value] {
+        self.values.get(name).map(Vec::as_slice).unwrap_or_default()
+    }
+
+    pub fn str(&self, name: &str) -> Option<&str> {
+        self.values(name).first().and_then(Value::as_str)
+    }
+
+    pub fn strs(&self, name: &str) -> Vec<&str> {
+        self.values(name).iter().filter_map(Value::as_str).collect()
+    }
+
+    pub fn numbers(&self, name: &str) -> Vec<f64> {
+        self.values(name).iter().filter_map(Value::as_number).collect()
+    }
+
+    pub fn integer(&self, name: &str) -> Option<i64> {
+        self.values(name).first().and_then(Value::as_integer)
+    }
+
+    pub fn flag(&self, long: &str) -> bool {
+        self.flags.contains(long)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn words(line: &str) -> Vec<String> {
+        line.split_whitespace().map(str::to_string).collect()
+    }
+
+    fn calc() -> Spec {
+        let numbers = || Spec::new().arg(Arg::new("number", ArgKind::Number).variadic());
+        Spec::new().subcommand("add", "Adds.", numbers()).subcommand("sub", "Subtracts.", numbers())
+    }
+
+    #[test]
+    fn subcommands_and_typed_positionals() {
+        let args = calc().parse(words("add 1 -2.5"), &[]).unwrap();
+        assert_eq!(args.subcommand(), Some("add"));
+        assert_eq!(args.numbers("number"), vec![1.0, -2.5]);
+        assert_eq!(calc().parse(words("add 1 x"), &[]).unwrap_err(), "'x' is not a number for <number>");
+        assert_eq!(calc().parse(words("add"), &[]).unwrap_err(), "missing argument <number>");
+        assert_eq!(calc().parse(words("pow 2"), &[]).unwrap_err(), "unknown subcommand 'pow' (expected one of add, sub)");
+    }
+
+    #[test]
+    fn flags_and_the_end_of_flags() {
+        let spec = Spec::new()
+            .flag(Flag::new("clear", "Clears.").short('c'))
+            .flag(Flag::new("all", "Everything."))
+            .arg(Arg::new("rest", ArgKind::Text).optional().variadic());
+        let args = spec.parse(words("-c a -- -b --all"), &[]).unwrap();
+        assert!(args.flag("clear"));
+        assert!(!args.flag("all"));
+        assert_eq!(args.strs("rest"), vec!["a", "-b", "--all"]);
+        assert_eq!(spec.parse(words("-x"), &[]).unwrap_err(), "unknown flag '-x'");
+        assert_eq!(spec.usage("history"), "history [-c|--clear] [--all] [rest]...");
+        assert!(spec.parse(words("-cc"), &[]).unwrap().flag("clear"));
+    }
+
+    #[test]
+    fn variadic_arguments_leave_room_for_later_ones() {
+        let spec = Spec::new().arg(Arg::new("source", ArgKind::Path).variadic()).arg(Arg::new("dest", ArgKind::Path));
+        let args = spec.parse(words("a b c"), &[]).unwrap();
+        assert_eq!(args.strs("source"), vec!["a", "b"]);
+        assert_eq!(args.str("dest"), Some("c"));
+        assert_eq!(spec.parse(words("a"), &[]).unwrap_err(), "missing argument <dest>");
+        assert_eq!(spec.usage("cp"), "cp <source>... <dest>");
+    }
+
+    #[test]
+    fn usage_and_completion_come_from_the_spec() {
+        assert_eq!(calc().usage("calc"), "calc add <number>...\ncalc sub <number>...");
+        assert_eq!(calc().complete(&[], "", &[]), vec!["add", "sub"]);
+        let help = Spec::new().arg(Arg::new("command", ArgKind::Command).optional());
+        let commands = vec!["calc".to_string(), "help".to_string()];
+        assert_eq!(help.complete(&[], "", &commands), commands);
+        assert!(help.parse(words("nope"), &commands).is_err());
+    }
+}
@@ -1,8 +1,18 @@
 use crate::utils::*;
+use crate::history::HISTORY;
+use crate::args::{Arg, ArgKind, Args, Flag, Spec};
+use crate::file_cmds::init_file_cmds;
+use crate::io::{Buffer, Io, Stdin};
+use crate::parser::{parse, Compound, Connector, List, Pipeline, Redirect, SimpleCommand, Stage};
+use crate::script::{call_function, define_function, get_function, init_script_cmds, positional_args, returning, run_file, script_name};
+use crate::shell::{expand_word, split_word, Expansion};
+use crate::glob::{glob, matches};
+use crate::lexer::{tokenize, Direction, Token, Word, WordPart};
+use crate::env::{is_valid_name, split_assignment, ENV};
+use crate::vfs::{absolute, FileSystem, VFS};
 use wasm_bindgen::prelude::*;
-use comma::parse_command;
 use web_sys::CanvasRenderingContext2d;
-use std::{sync::Mutex, collections::HashMap, any::Any, future::Future, sync::Arc};
+use std::{sync::Mutex, collections::HashMap, future::Future, pin::Pin, sync::Arc};
 use once_cell::sync::Lazy;
 use eval::eval;
 //use url::{Url, ParseError};
@@ -26,238 +36,851 @@ extern "C" {
     fn log_many(a: &str, b: &str);
 }
 
+#[allow(unused_macros)]
 macro_rules! console_log {
     // Note that this is using the `log` function imported above during
     // `bare_bones`
     ($($t:tt)*) => (log(&format_args!($($t)*).to_string()));
 }
-struct CmdContainer<'a, F: ?Sized + 'a>
-where
-    F: Fn(Vec<String>, &CanvasRenderingContext2d) -> Box<dyn Future<Output = Result<JsValue, JsValue>>>,
-{
-    func: &'a F,
-}
+// 0 when a command succeeded, anything else when it failed
+pub type Status = i32;
+
+pub const EXIT_SUCCESS: Status = 0;
+pub const EXIT_FAILURE: Status = 1;
+// the command was used wrongly, or the line couldn't be parsed
+pub const EXIT_USAGE: Status = 2;
+// the file isn't something that can be run
+pub const EXIT_NOT_EXECUTABLE: Status = 126;
+pub const EXIT_NOT_FOUND: Status = 127;
+
+// the status of the last pipeline, shown by `$?`
+pub static LAST_STATUS: Lazy<Mutex<Status>> = Lazy::new(|| Mutex::new(EXIT_SUCCESS));
+
+pub type CmdFuture<'a> = Pin<Box<dyn Future<Output = Status> + 'a>>;
+
+// A builtin command. `run` returns a boxed future so the trait stays object
+// safe and commands can live side by side in the registry.
+pub trait Command: Send + Sync {
+    fn name(&self) -> &'static str;
+    fn summary(&self) -> &'static str;
 
-impl<F> CmdContainer<'static, F>
-where
-    F: Fn(Vec<String>, &CanvasRenderingContext2d) -> Result<JsValue, JsValue>,
-{
-    fn new(func: F) -> CmdContainer<'static, F> {
-        CmdContainer { func: &func }
+    // What the command accepts. Input is checked against it before `run`, and
+    // `help <command>` and Tab completion are generated from it.
+    fn spec(&self) -> Spec {
+        Spec::new()
     }
+
+    fn run<'a>(&'a self, args: Args, io: &'a mut Io) -> CmdFuture<'a>;
 }
 
-trait CmdCaller {
-    fn call(&self, args: Vec<String>, context: &CanvasRenderingContext2d) -> Box<dyn Future<Output = Result<JsValue, JsValue>>>;
+static COMMANDS: Lazy<Mutex<HashMap<String, Arc<dyn Command>>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
+// puts back the builtins and the status a session starts with
+#[cfg(test)]
+pub fn reset() {
+    COMMANDS.lock().unwrap().clear();
+    *LAST_STATUS.lock().unwrap() = EXIT_SUCCESS;
+    init_cmd();
 }
 
-impl<F> CmdCaller for CmdContainer<'static, F>
-where
-    F: Fn(Vec<String>, &CanvasRenderingContext2d) -> Box<dyn Future<Output = Result<JsValue, JsValue>>>,
-{
-    async fn call(&self, args: Vec<String>, context: &CanvasRenderingContext2d) -> Box<dyn Future<Output = Result<JsValue, JsValue>>> {
-        // Call the function inside the CmdContainer
-        (self.func)(args, &context)
-    }
+// The shell's state is global, so tests that run commands take turns, each
+// starting from a fresh shell. A module holding global state has a `reset`
+// next to it, called from here.
+#[cfg(test)]
+pub fn fresh_shell() -> std::sync::MutexGuard<'static, ()> {
+    static SHELL: Mutex<()> = Mutex::new(());
+    let turn = SHELL.lock().unwrap_or_else(std::sync::PoisonError::into_inner);
+    reset();
+    crate::env::reset();
+    crate::vfs::reset();
+    crate::script::reset();
+    turn
 }
 
-pub static USER: Lazy<Mutex<&str>> = Lazy::new(|| Mutex::new("guest"));
-pub static HOST: Lazy<Mutex<&str>> = Lazy::new(|| Mutex::new("local"));
-pub static CWD: Lazy<Mutex<&str>> = Lazy::new(|| Mutex::new("~/"));
+pub fn register_cmd(command: impl Command + 'static) {
+    COMMANDS.lock().unwrap().insert(command.name().to_string(), Arc::new(command));
+}
 
-static COMMANDS: Lazy<Mutex<HashMap<String, Box<dyn CmdCaller + Send + Sync>>>> = Lazy::new(|| Mutex::new(HashMap::new()));
-static COMMANDS_HELP: Lazy<Mutex<HashMap<String, Vec<&str>>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+pub fn get_cmd(name: &str) -> Option<Arc<dyn Command>> {
+    COMMANDS.lock().unwrap().get(&name.to_lowercase()).cloned()
+}
 
-pub fn init_cmd() {
-    let mut map = COMMANDS.lock().unwrap();
-    let mut help_map = COMMANDS_HELP.lock().unwrap();
-    map.insert("help".to_string(), Box::new(CmdContainer { func: &help }));
-    help_map.insert("help".to_string(), vec!("Displays help.", "\nUsage: help [?subcommand]"));
-    /*map.insert("echo".to_string(), Arc::new(echo));
-    help_map.insert("echo".to_string(), vec!("Prints input to the console.", "\nUsage: echo \"string\""));
-    map.insert("calc".to_string(), Arc::new(calc));
-    help_map.insert("calc".to_string(), vec!("Performs operations on 2 or more numbers.", "\nUsage: calc [operation] [number 1, 2, 3...]"));
-    map.insert("evl".to_string(), Arc::new(evl));
-    help_map.insert("evl".to_string(), vec!("Evaluates an expression.", "\nUsage: evl \"expression\""));
-    //map.insert("import".to_string(), |arg, ctx| import(arg, ctx));
-    //help_map.insert("import".to_string(), "Imports remote commands. Internet required.".to_string());
-    map.insert("abacus".to_string(), Box::new(abacus));
-    help_map.insert("abacus".to_string(), vec!("Advanced mathematical operations.", "Implements multiple meval.\nUsage: abacus [operation] \"args\""));*/
-    
-    drop(map);
-    drop(help_map);
+// every registered command, sorted by name
+pub fn list_cmds() -> Vec<Arc<dyn Command>> {
+    let mut cmds: Vec<Arc<dyn Command>> = COMMANDS.lock().unwrap().values().cloned().collect();
+    cmds.sort_by_key(|command| command.name());
+    cmds
 }
 
-#[wasm_bindgen]
-pub async fn pass_cmd(cmd_str: &str, context: &CanvasRenderingContext2d) -> Result<JsValue, JsValue> {
-    lock_input();
-    if cmd_str.trim().is_empty() {return Ok(false.into());}
-    if let Some(mut args) = parse_command(cmd_str) {
-    let cmd = args.remove(0);
+pub fn cmd_names() -> Vec<String> {
+    list_cmds().iter().map(|command| command.name().to_string()).collect()
+}
+
+pub fn init_cmd() {
+    register_cmd(Help);
+    register_cmd(History);
+    register_cmd(Echo);
+    register_cmd(Calc);
+    register_cmd(Evl);
+    register_cmd(Grep);
+    register_cmd(Export);
+    register_cmd(Unset);
+    register_cmd(Env);
+    register_cmd(Set);
+    init_file_cmds();
+    init_script_cmds();
+    //register_cmd(Import);
+    register_cmd(Abacus);
+}
 
-    draw_text("\n", &context);
-    let cmds = COMMANDS.lock().unwrap();
-    
-    if let Some(command) = cmds.get(&cmd.to_string().to_lowercase()) {
-        async {
-            let _ = (*command).call(args, &context);
-        }.await;
-        drop(cmds);
+// Completes the word before the cursor: a command name for the first word of
+// a pipeline stage, otherwise whatever that command's spec allows next.
+// Returns the sorted candidates that start with the word.
+pub fn complete_cmd(line: &str) -> Vec<String> {
+    let Some((words, word)) = current_command(line) else { return Vec::new() };
+    let mut candidates = if words.is_empty() {
+        cmd_names()
     } else {
-        draw_text(r#"\#FFC0C0Unrecognized command. Type 'help' for a list of commands."#, &context);
-        return Ok(true.into());
-    }
-} else {
-    draw_text(r#"
-\#FFC0C0Error parsing arguments. Is there an end quote missing?"#, &context)
+        match get_cmd(&words[0]) {
+            Some(command) => command.spec().complete(&words[1..], &word, &cmd_names()),
+            None => Vec::new(),
+        }
+    };
+    candidates.retain(|candidate| candidate.starts_with(&word));
+    candidates.sort();
+    candidates.dedup();
+    candidates
 }
 
-Ok(true.into())
+// keywords a command can follow, like the `do` in `for x in a; do ca`
+const LEADING_KEYWORDS: [&str; 9] = ["if", "then", "elif", "else", "while", "until", "do", "{", "!"];
+
+// The command being typed at the end of `line`, read the way the parser
+// reads it: its words so far, and the word the cursor is in, which is empty
+// after a space. Redirects and their files are left out. None when the line
+// doesn't lex yet, as in an open quote, or the word being typed isn't plain
+// text.
+fn current_command(line: &str) -> Option<(Vec<String>, String)> {
+    let tokens = tokenize(line).ok()?;
+    let start = tokens.iter().rposition(|(token, _)| matches!(token, Token::Operator(_))).map_or(0, |i| i + 1);
+    let mut words = Vec::new();
+    let mut last_is_word = false;
+    let mut tokens = tokens[start..].iter();
+    while let Some((token, _)) = tokens.next() {
+        last_is_word = false;
+        match token {
+            Token::Word(word) if words.is_empty() && word.literal().is_some_and(|word| LEADING_KEYWORDS.contains(&word)) => {}
+            Token::Word(word) => {
+                words.push(word);
+                last_is_word = true;
+            }
+            // a redirect, and the file after it
+            _ => {
+                tokens.next();
+            }
+        }
+    }
+    let word = match last_is_word && !line.ends_with(char::is_whitespace) {
+        true => words.pop()?.literal()?.to_string(),
+        false => String::new(),
+    };
+    // earlier words only need their text, quoted or not
+    let text = |word: &Word| word.parts.iter().map(|part| match part {
+        WordPart::Literal(text) | WordPart::Quoted(text) => text.as_str(),
+        _ => "",
+    }).collect();
+    Some((words.into_iter().map(text).collect(), word))
 }
 
+// Runs a command line: pipelines separated by `;`, `&&`, `||` or newlines,
+// and the loops and conditionals built from them. Resolves to the status of
+// the last command run.
 #[wasm_bindgen]
-pub async fn help(args: Vec<String>, context: &CanvasRenderingContext2d) -> Result<JsValue, JsValue> {
-    let cmds_help = COMMANDS_HELP.lock().unwrap();
+pub async fn pass_cmd(cmd_str: &str, context: &CanvasRenderingContext2d) -> Result<JsValue, JsValue> {
+    lock_input();
+    if cmd_str.trim().is_empty() {return Ok((*LAST_STATUS.lock().unwrap()).into());}
 
-    if let Some(element) = args.get(0) {
-        match element.as_str() {
-            "about" => {
-                draw_text(r#"TODO"#, &context);
-            },
-            _ => {
-                if let Some(help) = cmds_help.get(element) {
-                    draw_text(&format!("↳ {} - {}\n", element, help.get(0).expect("couldn't get help")), &context);
-                    for i in 0..help.len() {
-                        draw_text(&format!("↳ {} - {}\n", element, help.get(i).expect("couldn't get help")), &context);
-                    }
-                } else {
-                    draw_text(r#"\#FFC0C0Unrecognized command/subcommand. Valid subcommands are 'about'."#, &context);
-                }
+    draw_text("\n", context);
+
+    let list = match parse(cmd_str) {
+        Ok(list) => list,
+        Err(err) => {
+            Io::terminal(context).error(&format!("{}\n{}", err, err.caret(cmd_str)));
+            *LAST_STATUS.lock().unwrap() = EXIT_USAGE;
+            return Ok(EXIT_USAGE.into());
+        }
+    };
+    let status = run_list(&list, &mut Io::terminal(context)).await;
+    VFS.lock().unwrap().sync();
+
+    Ok(status.into())
+}
+
+// Runs pipelines one after another, each only if its connector says so,
+// with `io` as the streams they use unless they redirect. Stops early when
+// `return` is used.
+pub async fn run_list(list: &List, io: &mut Io) -> Status {
+    let mut status = EXIT_SUCCESS;
+    for (connector, pipeline) in list {
+        let run = match connector {
+            Connector::Then => true,
+            Connector::And => status == EXIT_SUCCESS,
+            Connector::Or => status != EXIT_SUCCESS,
+        };
+        if run {
+            // keeps the output of consecutive commands on separate lines
+            io.stdout.end_line();
+            io.stderr.end_line();
+            status = run_pipeline(pipeline, io).await;
+            *LAST_STATUS.lock().unwrap() = status;
+            if returning() {
+                break;
             }
         }
-    } else {
-        cmds_help.iter().for_each(|(command, _)| {
-            if let Some(help) = cmds_help.get(command) {
-            draw_text(&format!("↳ {} - {}\n", command, help.get(0).expect("couldn't get help")), &context);
+    }
+    status
+}
+
+// Each stage of a pipeline gets the previous stage's output as its stdin;
+// only the last one writes to `io`.
+async fn run_pipeline(pipeline: &Pipeline, io: &mut Io) -> Status {
+    let mut input: Option<String> = None;
+    let mut status = EXIT_SUCCESS;
+    for (i, stage) in pipeline.commands.iter().enumerate() {
+        let mut io = io.share();
+        if let Some(input) = input.take() {
+            io.stdin = Stdin::from_text(&input);
         }
-    });
+        let captured = if i + 1 < pipeline.commands.len() {
+            let buffer = Buffer::new();
+            io.stdout = Box::new(buffer.clone());
+            Some(buffer)
+        } else {
+            None
+        };
+        status = run_stage(stage, &mut io).await;
+        input = captured.map(|buffer| buffer.take());
+    }
+    status
 }
-    drop(cmds_help);
 
-    Ok(true.into())
+// Boxed, since functions and compound commands run stages inside stages.
+pub fn run_stage<'a>(stage: &'a Stage, io: &'a mut Io) -> CmdFuture<'a> {
+    Box::pin(async move {
+        match stage {
+            Stage::Simple(command) => run_cmd(command, io).await,
+            Stage::Compound(compound, redirects) => {
+                let outputs = match redirect(redirects, io).await {
+                    Ok(outputs) => outputs,
+                    Err(err) => {
+                        io.error(&err);
+                        return EXIT_FAILURE;
+                    }
+                };
+                let status = match run_compound(compound, io).await {
+                    Ok(status) => status,
+                    Err(err) => {
+                        io.error(&format!("buudunn: {}", err));
+                        EXIT_FAILURE
+                    }
+                };
+                fill(outputs, io, status)
+            }
+            Stage::Function(name, body) => {
+                define_function(name, body.clone());
+                EXIT_SUCCESS
+            }
+        }
+    })
 }
 
-#[wasm_bindgen]
-pub async fn calc(args: Vec<String>, context: &CanvasRenderingContext2d) -> Result<JsValue, JsValue> {    if let Some(_element) = args.get(0) {
-    match args[0].to_lowercase().as_str() {
-        "add" => {
-            if let Some(_element) = args.get(1) {
-            let mut number: f64 = 0.0;
-            for i in 1..args.len() {
-                if let Ok(curr_number) = args[i].to_string().parse::<f64>() {
-                    number += curr_number;
-                } else {
-                    draw_text(r#"\#FFC0C0One or more arguments are not a number."#, &context);
-                    return Ok(true.into());
+async fn run_compound(compound: &Compound, io: &mut Io) -> Result<Status, String> {
+    let mut status = EXIT_SUCCESS;
+    match compound {
+        Compound::Group(list) => status = run_list(list, io).await,
+        Compound::If { branches, otherwise } => {
+            for (condition, body) in branches {
+                let tested = run_list(condition, io).await;
+                if returning() {
+                    return Ok(tested);
+                }
+                if tested == EXIT_SUCCESS {
+                    return Ok(run_list(body, io).await);
                 }
             }
-            draw_text(&format!("{}", number), &context);
-        } else {
-            draw_text(r#"\#FFC0C0No arguments specified!"#, &context);
+            if let Some(body) = otherwise {
+                status = run_list(body, io).await;
+            }
         }
-        },
-        "sub" => {
-            if let Some(_element) = args.get(1) {
-                let mut number: f64 = 0.0;
-                for i in 1..args.len() {
-                    if let Ok(curr_number) = args[i].to_string().parse::<f64>() {
-                        number -= curr_number;
-                    } else {
-                        draw_text(r#"\#FFC0C0One or more arguments are not a number."#, &context);
-                        return Ok(true.into());
-                    }
-                }
-                draw_text(&format!("{}", number), &context);
-            } else {
-                draw_text(r#"\#FFC0C0No arguments specified!"#, &context);
+        Compound::While { condition, body, until } => loop {
+            let tested = run_list(condition, io).await;
+            if returning() {
+                return Ok(tested);
+            }
+            if (tested == EXIT_SUCCESS) == *until {
+                break;
+            }
+            status = run_list(body, io).await;
+            if returning() {
+                break;
             }
         },
-        "mul" => {
-            if let Some(_element) = args.get(1) {
-                let mut number: f64 = 0.0;
-                if let Ok(curr_number) = args[1].to_string().parse::<f64>() {
-                number = curr_number;
-            } else {
-                draw_text(r#"\#FFC0C0The first argument is not a number."#, &context);
-                return Ok(true.into());
+        Compound::For { name, words, body } => {
+            let values = match words {
+                Some(words) => expand_args(words, io).await?,
+                None => positional_args(),
+            };
+            for value in values {
+                ENV.lock().unwrap().set(name, &value);
+                status = run_list(body, io).await;
+                if returning() {
+                    break;
+                }
             }
-
-                for i in 2..args.len() {
-                    if let Ok(curr_number) = args[i].to_string().parse::<f64>() {
-                        number = number * curr_number;
-                    } else {
-                        draw_text(r#"\#FFC0C0One or more arguments are not a number."#, &context);
-                        return Ok(true.into());
+        }
+        Compound::Case { word, arms } => {
+            let subject = expand(word, io).await?.map(|subject| subject.text).unwrap_or_default();
+            for (patterns, body) in arms {
+                for pattern in patterns {
+                    let matched = match expand(pattern, io).await? {
+                        Some(expansion) => match expansion.pattern {
+                            Some(pattern) => matches(&pattern, &subject),
+                            None => expansion.text == subject,
+                        },
+                        None => subject.is_empty(),
+                    };
+                    if matched {
+                        return Ok(run_list(body, io).await);
                     }
                 }
-                draw_text(&format!("{}", number), &context);
-            } else {
-                draw_text(r#"\#FFC0C0No arguments specified!"#, &context);
             }
-        },
-        "div" => {
-            if let Some(_element) = args.get(1) {
-                let mut number: f64 = 0.0;
-                if let Ok(curr_number) = args[1].to_string().parse::<f64>() {
-                number = curr_number;
-            } else {
-                draw_text(r#"\#FFC0C0The first argument is not a number."#, &context);
-                return Ok(true.into());
+        }
+    }
+    Ok(status)
+}
+
+// the value of a shell variable, of `$?`, or of a positional parameter
+fn lookup_var(name: &str) -> Option<String> {
+    match name {
+        "?" => Some(LAST_STATUS.lock().unwrap().to_string()),
+        "#" => Some(positional_args().len().to_string()),
+        "@" | "*" => Some(positional_args().join(" ")),
+        "0" => Some(script_name()),
+        name if name.starts_with(|ch: char| ch.is_ascii_digit()) => {
+            let n: usize = name.parse().ok()?;
+            positional_args().get(n.checked_sub(1)?).cloned()
+        }
+        name => ENV.lock().unwrap().get(name).map(str::to_string),
+    }
+}
+
+// A word with its variables and substitutions filled in.
+async fn expand(word: &Word, io: &mut Io) -> Result<Option<Expansion>, String> {
+    let outputs = substitutions(word, io).await?;
+    expand_word(word, lookup_var, &outputs)
+}
+
+// what each command substitution in `word` prints, in order
+async fn substitutions(word: &Word, io: &mut Io) -> Result<Vec<String>, String> {
+    let mut outputs = Vec::new();
+    for part in &word.parts {
+        if let WordPart::Command { source, .. } = part {
+            outputs.push(substitute(source, io).await?);
+        }
+    }
+    Ok(outputs)
+}
+
+// What the command line `source` prints, for `$(...)` and backticks. It runs
+// like a subshell: variables it sets and directories it changes to are put
+// back afterwards.
+async fn substitute(source: &str, io: &mut Io) -> Result<String, String> {
+    let list = parse(source).map_err(|err| format!("{}: {}", source, err))?;
+    let output = Buffer::new();
+    let mut nested = io.share();
+    nested.stdout = Box::new(output.clone());
+    let saved = ENV.lock().unwrap().clone();
+    run_list(&list, &mut nested).await;
+    *ENV.lock().unwrap() = saved;
+    Ok(output.take())
+}
+
+// The arguments `words` stand for: variables filled in, unquoted
+// expansions split at whitespace, and unquoted wildcards matched against
+// the VFS. A pattern matching nothing is kept as it is, like in bash.
+async fn expand_args(words: &[Word], io: &mut Io) -> Result<Vec<String>, String> {
+    let cwd = ENV.lock().unwrap().get("PWD").unwrap_or("/").to_string();
+    let mut args = Vec::new();
+    for word in words {
+        // `"$@"` is each positional parameter as a separate argument, kept
+        // whole
+        let parts: Vec<&WordPart> = word.parts.iter().filter(|part| **part != WordPart::Quoted(String::new())).collect();
+        if matches!(parts.as_slice(), [WordPart::Var { name, quoted: true }] if name == "@") {
+            args.extend(positional_args());
+            continue;
+        }
+        let outputs = substitutions(word, io).await?;
+        for expansion in split_word(word, lookup_var, &outputs)? {
+            let matched = match &expansion.pattern {
+                Some(pattern) => glob(&*VFS.lock().unwrap(), &cwd, pattern),
+                None => Vec::new(),
+            };
+            match matched.is_empty() {
+                true => args.push(expansion.text),
+                false => args.extend(matched),
             }
+        }
+    }
+    Ok(args)
+}
 
-                for i in 2..args.len() {
-                    if let Ok(curr_number) = args[i].to_string().parse::<f64>() {
-                        number = number / curr_number;
-                    } else {
-                        draw_text(r#"\#FFC0C0One or more arguments are not a number."#, &context);
-                        return Ok(true.into());
-                    }
-                }
-                draw_text(&format!("{}", number), &context);
-            } else {
-                draw_text(r#"\#FFC0C0No arguments specified!"#, &context);
+async fn run_cmd(command: &SimpleCommand, io: &mut Io) -> Status {
+    let expanded = async {
+        let args = expand_args(&command.words, io).await?;
+        let mut assignments = Vec::new();
+        for assignment in &command.assignments {
+            let value = expand(&assignment.value, io).await?.map(|value| value.text).unwrap_or_default();
+            assignments.push((assignment.name.clone(), value));
+        }
+        Ok::<_, String>((args, assignments))
+    };
+    let (mut args, assignments) = match expanded.await {
+        Ok(expanded) => expanded,
+        Err(err) => {
+            io.error(&format!("buudunn: {}", err));
+            return EXIT_FAILURE;
+        }
+    };
+    let outputs = match redirect(&command.redirects, io).await {
+        Ok(outputs) => outputs,
+        Err(err) => {
+            io.error(&err);
+            return EXIT_FAILURE;
+        }
+    };
+
+    // `NAME=value` words before the command only apply while it runs; on
+    // their own they set shell variables
+    if args.is_empty() {
+        let mut env = ENV.lock().unwrap();
+        for (name, value) in &assignments {
+            env.set(name, value);
+        }
+        return EXIT_SUCCESS;
+    }
+    let saved: Vec<(String, Option<String>)> = {
+        let mut env = ENV.lock().unwrap();
+        assignments.iter().map(|(name, value)| {
+            let old = env.get(name).map(str::to_string);
+            env.set(name, value);
+            (name.clone(), old)
+        }).collect()
+    };
+
+    let cmd = args.remove(0);
+    let status = run_args(&cmd, args, io).await;
+
+    {
+        let mut env = ENV.lock().unwrap();
+        for (name, old) in saved.into_iter().rev() {
+            match old {
+                Some(old) => env.set(&name, &old),
+                None => env.unset(&name),
             }
-        },
-        _ => draw_text(r#"\#FFC0C0Unrecognized operation. Valid operations are 'add', 'sub', 'mul', and 'div'."#, &context),
+        }
     }
-} else {
-    draw_text(r#"\#FFC0C0Missing operation. Valid operations are 'add', 'sub', 'mul', and 'div'."#, &context);
+    fill(outputs, io, status)
 }
-Ok(true.into())
+
+// Writes what the buffers from `redirect` captured to their files. Returns
+// `status`, or a failure if a file couldn't be written.
+fn fill(outputs: Vec<(String, String, Buffer)>, io: &mut Io, mut status: Status) -> Status {
+    for (path, target, buffer) in outputs {
+        let mut text = buffer.take();
+        // the terminal ends the last line itself, a file needs the newline
+        if !text.is_empty() && !text.ends_with('\n') {
+            text.push('\n');
+        }
+        let result = VFS.lock().unwrap().write(&path, text.as_bytes(), true);
+        if let Err(err) = result {
+            io.error(&format!("buudunn: {}: {}", target, err));
+            status = EXIT_FAILURE;
+        }
+    }
+    status
 }
 
-#[wasm_bindgen]
-pub async fn echo(args: Vec<String>, context: &CanvasRenderingContext2d) -> Result<JsValue, JsValue> {
-    draw_text(&format!("{}", args.join(" ")), &context);
-    Ok(true.into())
+// Points the command's streams at the files it redirects to, creating or
+// emptying the ones opened for writing first like bash does. Returns the
+// files to fill afterwards with what the buffers captured, and their names
+// as written. The streams are only changed once every file opened, so
+// errors still reach the screen.
+async fn redirect(redirects: &[Redirect], io: &mut Io) -> Result<Vec<(String, String, Buffer)>, String> {
+    let mut stdin = None;
+    let mut streams = Vec::new();
+    let mut outputs = Vec::new();
+    for redirect in redirects {
+        if redirect.fd > 2 {
+            return Err(format!("buudunn: {}: bad file descriptor", redirect.fd));
+        }
+        let target = match expand(&redirect.target, io).await {
+            Ok(Some(target)) => target.text,
+            Ok(None) => return Err("buudunn: ambiguous redirect".to_string()),
+            Err(err) => return Err(format!("buudunn: {}", err)),
+        };
+        let path = absolute(&target);
+        let mut vfs = VFS.lock().unwrap();
+        let opened = match redirect.direction {
+            Direction::Read => vfs.read(&path).map(|contents| String::from_utf8_lossy(&contents).into_owned()),
+            // nothing can be read back from a file opened for writing
+            direction => vfs.write(&path, b"", direction == Direction::Append).map(|_| String::new()),
+        };
+        let contents = opened.map_err(|err| format!("buudunn: {}: {}", target, err))?;
+        let buffer = Buffer::new();
+        match (redirect.fd, redirect.direction) {
+            (0, _) => stdin = Some(Stdin::from_text(&contents)),
+            // an output stream opened for reading takes nothing in, so what
+            // the command writes there goes nowhere
+            (fd, Direction::Read) => streams.push((fd, buffer)),
+            (fd, _) => {
+                streams.push((fd, buffer.clone()));
+                outputs.push((path, target, buffer));
+            },
+        }
+    }
+
+    if let Some(stdin) = stdin {
+        io.stdin = stdin;
+    }
+    for (fd, buffer) in streams {
+        match fd {
+            1 => io.stdout = Box::new(buffer),
+            _ => io.stderr = Box::new(buffer),
+        }
+    }
+    Ok(outputs)
 }
 
-#[wasm_bindgen]
-pub async fn evl(args: Vec<String>, context: &CanvasRenderingContext2d) -> Result<JsValue, JsValue> {
-    if let Some(element) = args.get(0) {
-        match eval(element) {
-            Ok(value) => draw_text(&format!("{}", value), &context),
-            Err(err) => draw_text(&format!("\\#FFC0C0Error in evaluating expression: {}", err), &context)
+async fn run_args(cmd: &str, args: Vec<String>, io: &mut Io) -> Status {
+    if let Some(body) = get_function(cmd) {
+        return call_function(cmd, &body, args, io).await;
+    }
+    if cmd.contains('/') {
+        return run_file(cmd, args, io).await;
+    }
+    // the registry lock is released before the command runs, so commands
+    // can look at the registry themselves
+    if let Some(command) = get_cmd(cmd) {
+        let spec = command.spec();
+        match spec.parse(args, &cmd_names()) {
+            Ok(args) => command.run(args, io).await,
+            Err(err) => {
+                io.error(&format!("{}: {}\nUsage: {}", command.name(), err, spec.usage(command.name()).replace('\n', "\n       ")));
+                EXIT_USAGE
+            }
         }
-        
     } else {
-        draw_text(r#"\#FFC0C0No expression given. Make sure it's wrapped in quotes."#, &context);
+        io.error(&format!("{}: command not found. Type 'help' for a list of commands.", cmd));
+        EXIT_NOT_FOUND
+    }
+}
+
+struct Help;
+
+impl Command for Help {
+    fn name(&self) -> &'static str { "help" }
+    fn summary(&self) -> &'static str { "Displays help." }
+
+    fn spec(&self) -> Spec {
+        Spec::new()
+            .subcommand("about", "Shows what Buudunn is.", Spec::new())
+            .arg(Arg::new("command", ArgKind::Command).optional())
+    }
+
+    fn run<'a>(&'a self, args: Args, io: &'a mut Io) -> CmdFuture<'a> {
+        Box::pin(help(args, io))
     }
-    Ok(true.into())
 }
+
+async fn help(args: Args, io: &mut Io) -> Status {
+    if args.subcommand() == Some("about") {
+        io.print(r#"TODO"#);
+    } else if let Some(command) = args.str("command").and_then(get_cmd) {
+        io.print(&format!("↳ {} - {}\n{}\n", command.name(), command.summary(), command.spec().help(command.name())));
+    } else {
+        for command in list_cmds() {
+            io.print(&format!("↳ {} - {}\n", command.name(), command.summary()));
+        }
+    }
+
+    EXIT_SUCCESS
+}
+
+struct History;
+
+impl Command for History {
+    fn name(&self) -> &'static str { "history" }
+    fn summary(&self) -> &'static str { "Lists previously entered commands. Re-run entries with !n, !-n or !!." }
+
+    fn spec(&self) -> Spec {
+        Spec::new()
+            .flag(Flag::new("clear", "Clears the history.").short('c'))
+            .arg(Arg::new("count", ArgKind::Integer).optional())
+    }
+
+    fn run<'a>(&'a self, args: Args, io: &'a mut Io) -> CmdFuture<'a> {
+        Box::pin(history(args, io))
+    }
+}
+
+async fn history(args: Args, io: &mut Io) -> Status {
+    let mut history = HISTORY.lock().unwrap();
+    if args.flag("clear") {
+        history.clear();
+        return EXIT_SUCCESS;
+    }
+
+    let entries = history.entries();
+    let count = args.integer("count").map(|count| count.max(0) as usize).unwrap_or(entries.len());
+    let lines: Vec<String> = entries.iter().enumerate().skip(entries.len().saturating_sub(count))
+        .map(|(i, entry)| format!("{:>5}  {}", i + 1, entry))
+        .collect();
+    io.print(&lines.join("\n"));
+    drop(history);
+
+    EXIT_SUCCESS
+}
+
+struct Calc;
+
+impl Command for Calc {
+    fn name(&self) -> &'static str { "calc" }
+    fn summary(&self) -> &'static str { "Performs operations on 2 or more numbers." }
+
+    fn spec(&self) -> Spec {
+        let numbers = || Spec::new().arg(Arg::new("number", ArgKind::Number).variadic());
+        Spec::new()
+            .subcommand("add", "Adds the numbers together.", numbers())
+            .subcommand("sub", "Subtracts the numbers from 0.", numbers())
+            .subcommand("mul", "Multiplies the numbers together.", numbers())
+            .subcommand("div", "Divides the first number by the rest.", numbers())
+    }
+
+    fn run<'a>(&'a self, args: Args, io: &'a mut Io) -> CmdFuture<'a> {
+        Box::pin(calc(args, io))
+    }
+}
+
+async fn calc(args: Args, io: &mut Io) -> Status {
+    // the spec guarantees at least one number
+    let numbers = args.numbers("number");
+    let rest = numbers.iter().skip(1);
+    let number = match args.subcommand() {
+        Some("add") => numbers.iter().sum(),
+        // every number is taken away from 0, the first one too
+        Some("sub") => numbers.iter().fold(0.0, |number, curr_number| number - curr_number),
+        Some("mul") => numbers.iter().product(),
+        _ => rest.fold(numbers[0], |number, curr_number| number / curr_number),
+    };
+    io.print(&format!("{}", number));
+    EXIT_SUCCESS
+}
+
+struct Echo;
+
+impl Command for Echo {
+    fn name(&self) -> &'static str { "echo" }
+    fn summary(&self) -> &'static str { "Prints input to the console." }
+
+    fn spec(&self) -> Spec {
+        Spec::new().arg(Arg::new("text", ArgKind::Text).optional().variadic())
+    }
+
+    fn run<'a>(&'a self, args: Args, io: &'a mut Io) -> CmdFuture<'a> {
+        Box::pin(echo(args, io))
+    }
+}
+
+async fn echo(args: Args, io: &mut Io) -> Status {
+    io.print(&args.strs("text").join(" "));
+    EXIT_SUCCESS
+}
+
+struct Evl;
+
+impl Command for Evl {
+    fn name(&self) -> &'static str { "evl" }
+    fn summary(&self) -> &'static str { "Evaluates an expression, or each line piped in." }
+
+    fn spec(&self) -> Spec {
+        Spec::new().arg(Arg::new("expression", ArgKind::Text).optional())
+    }
+
+    fn run<'a>(&'a self, args: Args, io: &'a mut Io) -> CmdFuture<'a> {
+        Box::pin(evl(args, io))
+    }
+}
+
+async fn evl(args: Args, io: &mut Io) -> Status {
+    let expressions: Vec<String> = match args.str("expression") {
+        Some(expression) => vec![expression.to_string()],
+        None if !io.stdin.is_terminal() => io.stdin.read_to_string().lines().filter(|line| !line.trim().is_empty()).map(str::to_string).collect(),
+        None => {
+            io.error("No expression given. Wrap it in quotes or pipe it in.");
+            return EXIT_FAILURE;
+        }
+    };
+    let mut results = Vec::new();
+    let mut status = EXIT_SUCCESS;
+    for expression in expressions {
+        match eval(&expression) {
+            Ok(value) => results.push(format!("{}", value)),
+            Err(err) => {
+                io.error(&format!("Error in evaluating expression: {}", err));
+                status = EXIT_FAILURE;
+            }
+        }
+    }
+    io.print(&results.join("\n"));
+    status
+}
+
+struct Grep;
+
+impl Command for Grep {
+    fn name(&self) -> &'static str { "grep" }
+    fn summary(&self) -> &'static str { "Prints the lines piped in that contain a pattern." }
+
+    fn spec(&self) -> Spec {
+        Spec::new()
+            .flag(Flag::new("ignore-case", "Matches upper and lower case alike.").short('i'))
+            .flag(Flag::new("invert-match", "Prints the lines that don't match instead.").short('v'))
+            .arg(Arg::new("pattern", ArgKind::Text))
+    }
+
+    fn run<'a>(&'a self, args: Args, io: &'a mut Io) -> CmdFuture<'a> {
+        Box::pin(grep(args, io))
+    }
+}
+
+async fn grep(args: Args, io: &mut Io) -> Status {
+    if io.stdin.is_terminal() {
+        io.error("Nothing to search. Pipe output into grep, e.g. 'help | grep calc'.");
+        return EXIT_FAILURE;
+    }
+    let ignore_case = args.flag("ignore-case");
+    let fold = |text: &str| if ignore_case { text.to_lowercase() } else { text.to_string() };
+    let pattern = fold(args.str("pattern").unwrap_or_default());
+    let input = io.stdin.read_to_string();
+    let lines: Vec<&str> = input.lines()
+        .filter(|line| fold(line).contains(&pattern) != args.flag("invert-match"))
+        .collect();
+    io.print(&lines.join("\n"));
+    EXIT_SUCCESS
+}
+struct Export;
+
+impl Command for Export {
+    fn name(&self) -> &'static str { "export" }
+    fn summary(&self) -> &'static str { "Sets variables and passes them on to env." }
+
+    fn spec(&self) -> Spec {
+        Spec::new().arg(Arg::new("name[=value]", ArgKind::Variable).optional().variadic())
+    }
+
+    fn run<'a>(&'a self, args: Args, io: &'a mut Io) -> CmdFuture<'a> {
+        Box::pin(export(args, io))
+    }
+}
+
+async fn export(args: Args, io: &mut Io) -> Status {
+    let mut env = ENV.lock().unwrap();
+    let words = args.strs("name[=value]");
+    if words.is_empty() {
+        let lines: Vec<String> = env.exported().map(|(name, value)| format!("export {}=\"{}\"", name, value)).collect();
+        io.print(&lines.join("\n"));
+        return EXIT_SUCCESS;
+    }
+    let mut status = EXIT_SUCCESS;
+    for word in words {
+        match split_assignment(word) {
+            Some((name, value)) => env.export(name, Some(value)),
+            None if is_valid_name(word) => env.export(word, None),
+            None => {
+                io.error(&format!("export: `{}': not a valid identifier", word));
+                status = EXIT_FAILURE;
+            }
+        }
+    }
+    status
+}
+
+struct Unset;
+
+impl Command for Unset {
+    fn name(&self) -> &'static str { "unset" }
+    fn summary(&self) -> &'static str { "Removes variables." }
+
+    fn spec(&self) -> Spec {
+        Spec::new().arg(Arg::new("name", ArgKind::Variable).variadic())
+    }
+
+    fn run<'a>(&'a self, args: Args, io: &'a mut Io) -> CmdFuture<'a> {
+        Box::pin(unset(args, io))
+    }
+}
+
+async fn unset(args: Args, io: &mut Io) -> Status {
+    let mut env = ENV.lock().unwrap();
+    let mut status = EXIT_SUCCESS;
+    for name in args.strs("name") {
+        if is_valid_name(name) {
+            env.unset(name);
+        } else {
+            io.error(&format!("unset: `{}': not a valid identifier", name));
+            status = EXIT_FAILURE;
+        }
+    }
+    status
+}
+
+struct Env;
+
+impl Command for Env {
+    fn name(&self) -> &'static str { "env" }
+    fn summary(&self) -> &'static str { "Lists the exported variables." }
+
+    fn run<'a>(&'a self, _args: Args, io: &'a mut Io) -> CmdFuture<'a> {
+        Box::pin(env(io))
+    }
+}
+
+async fn env(io: &mut Io) -> Status {
+    let lines: Vec<String> = ENV.lock().unwrap().exported().map(|(name, value)| format!("{}={}", name, value)).collect();
+    io.print(&lines.join("\n"));
+    EXIT_SUCCESS
+}
+
+struct Set;
+
+impl Command for Set {
+    fn name(&self) -> &'static str { "set" }
+    fn summary(&self) -> &'static str { "Lists every shell variable. Set one with NAME=value." }
+
+    fn run<'a>(&'a self, _args: Args, io: &'a mut Io) -> CmdFuture<'a> {
+        Box::pin(set(io))
+    }
+}
+
+async fn set(io: &mut Io) -> Status {
+    let lines: Vec<String> = ENV.lock().unwrap().vars().map(|(name, value)| format!("{}='{}'", name, value.replace('\'', "'\\''"))).collect();
+    io.print(&lines.join("\n"));
+    EXIT_SUCCESS
+}
+
 /*
-fn import(args: Vec<String>, context: &CanvasRenderingContext2d) -> Result<(), Box<dyn std::error::Error>> {
-    draw_text("Sorry! This isn't finished yet!", context);
+fn import(args: Vec<String>, io: &mut Io) -> Result<(), Box<dyn std::error::Error>> {
+    io.print("Sorry! This isn't finished yet!");
     if let Some(element) = args.get(0) {
         match Url::parse(element) {
     Ok(_someurl) => {
@@ -266,27 +889,40 @@ fn import(args: Vec<String>, context: &CanvasRenderingContext2d) -> Result<(), B
     println!("{:#?}", resp);
     Ok(())
     },
-    Err(err) => Ok(draw_text(&format!("\\#FFC0C0Error in parsing URL: {}", err), &context))
+    Err(err) => Ok(io.error(&format!("Error in parsing URL: {}", err)))
     }
 } else {
-    Ok(draw_text(r#"\#FFC0C0No URL given. Make sure it's wrapped in quotes."#, &context))
+    Ok(io.error("No URL given. Make sure it's wrapped in quotes."))
 }
 }*/
 
-#[wasm_bindgen]
-pub async fn abacus(args: Vec<String>, context: &CanvasRenderingContext2d) -> Result<JsValue, JsValue> {
-    if let Some(_elem) = args.get(0) {
-        match args.get(0).expect("error").as_str() {
-            "eval" => {
-                if let Some(expr) = args.get(1) {
-                    match meval::eval_str(expr) {
-                        Ok(value) => draw_text(&format!("{}", value), &context),
-                        Err(err) => draw_text(&format!("\\#FFC0C0Couldn't evaluate expression: {}", err), &context)
-                    }
-                } else {
-                    draw_text(r#"\#FFC0C0No expression given! Is it wrapped in quotes?"#, &context);
-                }
-            },
+struct Abacus;
+
+impl Command for Abacus {
+    fn name(&self) -> &'static str { "abacus" }
+    fn summary(&self) -> &'static str { "Advanced mathematical operations." }
+
+    fn spec(&self) -> Spec {
+        Spec::new()
+            .subcommand("eval", "Evaluates an expression with meval.", Spec::new().arg(Arg::new("expression", ArgKind::Text)))
+    }
+
+    fn run<'a>(&'a self, args: Args, io: &'a mut Io) -> CmdFuture<'a> {
+        Box::pin(abacus(args, io))
+    }
+}
+
+async fn abacus(args: Args, io: &mut Io) -> Status {
+    if args.subcommand() == Some("eval") {
+        let expr = args.str("expression").unwrap_or_default();
+        match meval::eval_str(expr) {
+            Ok(value) => io.print(&format!("{}", value)),
+            Err(err) => {
+                io.error(&format!("Couldn't evaluate expression: {}", err));
+                return EXIT_FAILURE;
+            }
+        }
+    }
             /*"linear_solve" => {
                 if let Some(expr) = args.get(1) {
                     // Parse the input string to extract coefficients and constants
@@ -304,21 +940,16 @@ pub async fn abacus(args: Vec<String>, context: &CanvasRenderingContext2d) -> Re
     match vars.maximise(objective).using(default_solver).solve() {
         Ok(solution) => {
             for (varb, var) in varmap.iter() {
-                draw_text(&format!("{} = {}", varb, solution.value(*var)), &context);
+                io.print(&format!("{} = {}\n", varb, solution.value(*var)));
             }
         },
-        Err(err) => draw_text(&format!("\\#FFC0C0Error in parsing linear equation: {}", err), &context)
+        Err(err) => io.error(&format!("Error in parsing linear equation: {}", err))
     }
                 } else {
-                    draw_text(r#"\#FFC0C0No expression given! Is it wrapped in quotes?"#, &context);
+                    io.error("No expression given! Is it wrapped in quotes?");
                 }
             },*/
-            &_ => draw_text(r#"\#FFC0C0Invalid operation."#, &context)
-        }
-    } else {
-        draw_text(r#"\#FFC0C0No operation given."#, &context);
-    }
-    Ok(true.into())
+    EXIT_SUCCESS
 }
 /*
 fn parse_coefficients(input_string: &str) -> HashMap<String, f64> {
@@ -348,4 +979,97 @@ fn parse_coefficients(input_string: &str) -> HashMap<String, f64> {
         }
     }
     map
-}*/
\ No newline at end of file
+}*/
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{pin::pin, task::{Context, Poll, Waker}};
+
+    // what running `line` prints to either stream, with its status
+    fn run(line: &str) -> (Status, String) {
+        let list = parse(line).unwrap();
+        let output = Buffer::new();
+        let mut io = Io { stdin: Stdin::default(), stdout: Box::new(output.clone()), stderr: Box::new(output.clone()) };
+        // commands without network access never wait
+        let status = match pin!(run_list(&list, &mut io)).poll(&mut Context::from_waker(Waker::noop())) {
+            Poll::Ready(status) => status,
+            Poll::Pending => panic!("the command waited"),
+        };
+        (status, output.take())
+    }
+
+    #[test]
+    fn completion_looks_at_the_last_command() {
+        let current = |line: &str| current_command(line).map(|(words, word)| (words.join(" "), word));
+        assert_eq!(current("help | gr"), Some((String::new(), "gr".to_string())));
+        assert_eq!(current("help && ca"), Some((String::new(), "ca".to_string())));
+        assert_eq!(current("echo 'a|b' c"), Some(("echo a|b".to_string(), "c".to_string())));
+        assert_eq!(current("echo 'a\\' | ca"), Some((String::new(), "ca".to_string())));
+        assert_eq!(current("x=$(a | b) && ec"), Some((String::new(), "ec".to_string())));
+        assert_eq!(current("sleep 1 & he"), Some((String::new(), "he".to_string())));
+        assert_eq!(current("ls # | not this"), Some((String::new(), "ls".to_string())));
+        assert_eq!(current("for f in a; do ca"), Some((String::new(), "ca".to_string())));
+        assert_eq!(current("cat <in "), Some(("cat".to_string(), String::new())));
+        assert_eq!(current("cat <in"), Some(("cat".to_string(), String::new())));
+        assert_eq!(current("echo \"ab"), None);
+    }
+
+    #[test]
+    fn calc_subtracts_every_number_from_zero() {
+        let _turn = fresh_shell();
+        assert_eq!(run("calc sub 5 2"), (EXIT_SUCCESS, "-7".to_string()));
+        assert_eq!(run("calc div 8 2 2"), (EXIT_SUCCESS, "2".to_string()));
+    }
+
+    #[test]
+    fn substitution_runs_like_a_subshell() {
+        let _turn = fresh_shell();
+        assert_eq!(run("here=$(cd /tmp; echo ignored > /tmp/sub; pwd)\necho $here $PWD $(cat /tmp/sub)"), (EXIT_SUCCESS, "/tmp /home/guest ignored".to_string()));
+    }
+
+    #[test]
+    fn substitution_splits_unquoted_output() {
+        let _turn = fresh_shell();
+        assert_eq!(run("words() { echo $#; }\nwords $(echo a b) \"$(echo a b)\" x$(echo) `echo c  d`"), (EXIT_SUCCESS, "6".to_string()));
+        assert_eq!(run("for word in $(echo a b) `echo c` d; do echo $word; done"), (EXIT_SUCCESS, "a\nb\nc\nd".to_string()));
+    }
+
+    #[test]
+    fn arithmetic_reads_shell_variables() {
+        let _turn = fresh_shell();
+        assert_eq!(run("count=3\necho $(( (count * 7) / 2 ))"), (EXIT_SUCCESS, "10".to_string()));
+    }
+
+    #[test]
+    fn arithmetic_division_by_zero_fails() {
+        let _turn = fresh_shell();
+        assert_eq!(run("echo $((1 / 0)) after"), (EXIT_FAILURE, "buudunn: 1 / 0: division by 0\n".to_string()));
+    }
+
+    #[test]
+    fn tilde_globs_give_real_paths() {
+        let _turn = fresh_shell();
+        assert_eq!(run("echo > ~/tilde.txt\necho ~/tilde* \"~/tilde*\""), (EXIT_SUCCESS, "/home/guest/tilde.txt ~/tilde*".to_string()));
+    }
+
+    #[test]
+    fn redirects_keep_their_direction() {
+        let _turn = fresh_shell();
+        assert_eq!(run("echo kept 1>/tmp/redirect"), (EXIT_SUCCESS, String::new()));
+        assert_eq!(run("echo gone 1</tmp/redirect"), (EXIT_SUCCESS, String::new()));
+        assert_eq!(run("cat 2</tmp/redirect"), (EXIT_SUCCESS, String::new()));
+        assert_eq!(run("cat 0>/tmp/empty"), (EXIT_SUCCESS, String::new()));
+        assert_eq!(run("cat </tmp/redirect"), (EXIT_SUCCESS, "kept\n".to_string()));
+        assert_eq!(VFS.lock().unwrap().read("/tmp/empty").unwrap(), b"");
+        assert_eq!(parse("echo hi 3>/tmp/redirect").unwrap_err().message, "3: bad file descriptor");
+        assert_eq!(run("echo nothing 2<missing"), (EXIT_FAILURE, "buudunn: missing: No such file or directory\n".to_string()));
+    }
+
+    #[test]
+    fn redirects_into_directories_fail() {
+        let _turn = fresh_shell();
+        assert_eq!(run("echo dir > /home/guest"), (EXIT_FAILURE, "buudunn: /home/guest: Is a directory\n".to_string()));
+        assert_eq!(run("echo mount >> /tmp"), (EXIT_FAILURE, "buudunn: /tmp: Is a directory\n".to_string()));
+        assert!(VFS.lock().unwrap().is_dir("/tmp"));
+    }
+}
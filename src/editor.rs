@@ -0,0 +1,225 @@
+// The command line being typed at the prompt. `cursor` is an index into
+// `buffer` counted in characters; every method returns whether anything
+// changed so callers know when to redraw.
+#[derive(Default)]
+pub struct LineEditor {
+    buffer: Vec<char>,
+    cursor: usize,
+}
+
+impl LineEditor {
+    pub fn new() -> LineEditor {
+        LineEditor::default()
+    }
+
+    pub fn text(&self) -> String {
+        self.buffer.iter().collect()
+    }
+
+    pub fn text_before_cursor(&self) -> String {
+        self.buffer[..self.cursor].iter().collect()
+    }
+
+    pub fn set_text(&mut self, text: &str) -> bool {
+        self.buffer = text.chars().collect();
+        self.cursor = self.buffer.len();
+        true
+    }
+
+    pub fn clear(&mut self) -> bool {
+        self.set_text("")
+    }
+
+    pub fn insert(&mut self, text: &str) -> bool {
+        for ch in text.chars() {
+            self.buffer.insert(self.cursor, ch);
+            self.cursor += 1;
+        }
+        !text.is_empty()
+    }
+
+    // replaces the `count` characters before the cursor, e.g. with a completion
+    pub fn replace_before_cursor(&mut self, count: usize, text: &str) -> bool {
+        let start = self.cursor.saturating_sub(count);
+        self.buffer.drain(start..self.cursor);
+        self.cursor = start;
+        self.insert(text);
+        true
+    }
+
+    pub fn backspace(&mut self) -> bool {
+        if self.cursor == 0 {
+            return false;
+        }
+        self.cursor -= 1;
+        self.buffer.remove(self.cursor);
+        true
+    }
+
+    pub fn delete(&mut self) -> bool {
+        if self.cursor == self.buffer.len() {
+            return false;
+        }
+        self.buffer.remove(self.cursor);
+        true
+    }
+
+    pub fn left(&mut self) -> bool {
+        self.move_to(self.cursor.saturating_sub(1))
+    }
+
+    pub fn right(&mut self) -> bool {
+        self.move_to((self.cursor + 1).min(self.buffer.len()))
+    }
+
+    pub fn home(&mut self) -> bool {
+        self.move_to(0)
+    }
+
+    pub fn end(&mut self) -> bool {
+        self.move_to(self.buffer.len())
+    }
+
+    // to the start of the current or previous word
+    pub fn word_left(&mut self) -> bool {
+        let mut i = self.cursor;
+        while i > 0 && !is_word_char(self.buffer[i - 1]) {
+            i -= 1;
+        }
+        while i > 0 && is_word_char(self.buffer[i - 1]) {
+            i -= 1;
+        }
+        self.move_to(i)
+    }
+
+    // to the end of the current or next word
+    pub fn word_right(&mut self) -> bool {
+        let mut i = self.cursor;
+        while i < self.buffer.len() && !is_word_char(self.buffer[i]) {
+            i += 1;
+        }
+        while i < self.buffer.len() && is_word_char(self.buffer[i]) {
+            i += 1;
+        }
+        self.move_to(i)
+    }
+
+    fn move_to(&mut self, cursor: usize) -> bool {
+        let moved = cursor != self.cursor;
+        self.cursor = cursor;
+        moved
+    }
+}
+
+fn is_word_char(ch: char) -> bool {
+    ch.is_alphanumeric() || ch == '_'
+}
+
+// State of a Ctrl+R reverse incremental search through the history.
+pub struct ReverseSearch {
+    pub query: String,
+    // index of the history entry currently matched
+    pub found: Option<usize>,
+    pub failed: bool,
+    // the command line to put back if the search is cancelled
+    pub original: String,
+}
+
+impl ReverseSearch {
+    pub fn new(original: &str) -> ReverseSearch {
+        ReverseSearch { query: String::new(), found: None, failed: false, original: original.to_string() }
+    }
+
+    // the line shown in place of the prompt while searching
+    pub fn prompt(&self, entries: &[String]) -> String {
+        let found = self.found.and_then(|i| entries.get(i)).map(String::as_str).unwrap_or("");
+        let failed = if self.failed { "failed " } else { "" };
+        format!("({}reverse-i-search)`{}': {}", failed, self.query, found)
+    }
+
+    pub fn push(&mut self, text: &str, entries: &[String]) {
+        self.query.push_str(text);
+        // the current match may still match the longer query
+        let from = self.found.map(|i| i + 1).unwrap_or(entries.len());
+        self.search(entries, from);
+    }
+
+    pub fn pop(&mut self, entries: &[String]) {
+        self.query.pop();
+        self.search(entries, entries.len());
+    }
+
+    // Ctrl+R again: the next older match
+    pub fn older(&mut self, entries: &[String]) {
+        let from = self.found.unwrap_or(entries.len());
+        self.search(entries, from);
+    }
+
+    // looks for the newest entry before `before` containing the query
+    fn search(&mut self, entries: &[String], before: usize) {
+        if self.query.is_empty() {
+            self.failed = false;
+            return;
+        }
+        match entries[..before.min(entries.len())].iter().rposition(|entry| entry.contains(&self.query)) {
+            Some(i) => {
+                self.found = Some(i);
+                self.failed = false;
+            }
+            None => self.failed = true,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn insert_and_delete_in_the_middle() {
+        let mut editor = LineEditor::new();
+        editor.insert("calc ad 1 2");
+        for _ in 0..4 {
+            editor.left();
+        }
+        editor.insert("d");
+        assert_eq!(editor.text(), "calc add 1 2");
+        editor.home();
+        assert!(editor.delete());
+        assert!(!editor.backspace());
+        assert_eq!(editor.text(), "alc add 1 2");
+        assert_eq!(editor.text_before_cursor(), "");
+    }
+
+    #[test]
+    fn reverse_search_refines_and_cycles() {
+        let entries: Vec<String> = ["calc add 1 2", "help", "calc mul 3 4"].iter().map(|s| s.to_string()).collect();
+        let mut search = ReverseSearch::new("");
+        search.push("c", &entries);
+        assert_eq!(search.found, Some(2));
+        search.push("alc a", &entries);
+        assert_eq!(search.found, Some(0));
+        search.pop(&entries);
+        assert_eq!(search.found, Some(2));
+        search.older(&entries);
+        assert_eq!(search.found, Some(0));
+        search.older(&entries);
+        assert!(search.failed);
+        assert_eq!(search.prompt(&entries), "(failed reverse-i-search)`calc ': calc add 1 2");
+    }
+
+    #[test]
+    fn word_motion() {
+        let mut editor = LineEditor::new();
+        editor.insert("echo  hello world");
+        editor.word_left();
+        assert_eq!(editor.text_before_cursor(), "echo  hello ");
+        editor.word_left();
+        assert_eq!(editor.text_before_cursor(), "echo  ");
+        editor.home();
+        editor.word_right();
+        assert_eq!(editor.text_before_cursor(), "echo");
+        editor.word_right();
+        assert_eq!(editor.text_before_cursor(), "echo  hello");
+    }
+}
@@ -0,0 +1,134 @@
+use crate::prompt::default_ps1;
+use once_cell::sync::Lazy;
+use std::{collections::BTreeMap, sync::Mutex};
+
+pub static ENV: Lazy<Mutex<Env>> = Lazy::new(|| Mutex::new(Env::new()));
+
+// puts back the variables a session starts with
+#[cfg(test)]
+pub fn reset() {
+    *ENV.lock().unwrap() = Env::new();
+}
+
+#[derive(Clone)]
+struct Var {
+    value: String,
+    // exported variables are the ones `env` lists
+    exported: bool,
+}
+
+// The session's shell variables, sorted by name.
+#[derive(Clone)]
+pub struct Env {
+    vars: BTreeMap<String, Var>,
+}
+
+impl Env {
+    pub fn new() -> Env {
+        let mut env = Env { vars: BTreeMap::new() };
+        for (name, value) in [
+            ("USER", "guest"),
+            ("HOST", "local"),
+            ("HOME", "/home/guest"),
+            ("PWD", "/home/guest"),
+            ("PATH", "/bin"),
+            ("SHELL", "/bin/buudunn"),
+            ("TERM", "xterm-256color"),
+        ] {
+            env.export(name, Some(value));
+        }
+        env.export("PS1", Some(&default_ps1()));
+        env
+    }
+
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.vars.get(name).map(|var| var.value.as_str())
+    }
+
+    // sets a variable, keeping it exported if it already was
+    pub fn set(&mut self, name: &str, value: &str) {
+        match self.vars.get_mut(name) {
+            Some(var) => var.value = value.to_string(),
+            None => {
+                self.vars.insert(name.to_string(), Var { value: value.to_string(), exported: false });
+            }
+        }
+    }
+
+    // marks a variable as exported, setting it first when a value is given
+    pub fn export(&mut self, name: &str, value: Option<&str>) {
+        let value = value.or(self.get(name)).unwrap_or_default().to_string();
+        self.vars.insert(name.to_string(), Var { value, exported: true });
+    }
+
+    pub fn unset(&mut self, name: &str) {
+        self.vars.remove(name);
+    }
+
+    pub fn names(&self) -> Vec<String> {
+        self.vars.keys().cloned().collect()
+    }
+
+    pub fn vars(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.vars.iter().map(|(name, var)| (name.as_str(), var.value.as_str()))
+    }
+
+    pub fn exported(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.vars.iter().filter(|(_, var)| var.exported).map(|(name, var)| (name.as_str(), var.value.as_str()))
+    }
+
+    // PWD with HOME shortened to `~`, as shown in the prompt
+    pub fn short_pwd(&self) -> String {
+        let pwd = self.get("PWD").unwrap_or("/");
+        match self.get("HOME").filter(|home| !home.is_empty()) {
+            Some(home) if pwd == home => "~".to_string(),
+            Some(home) if pwd.starts_with(&format!("{}/", home.trim_end_matches('/'))) => format!("~{}", &pwd[home.trim_end_matches('/').len()..]),
+            _ => pwd.to_string(),
+        }
+    }
+}
+
+pub fn is_valid_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    matches!(chars.next(), Some(ch) if ch.is_ascii_alphabetic() || ch == '_') && chars.all(|ch| ch.is_ascii_alphanumeric() || ch == '_')
+}
+
+// Splits `NAME=value`, if `word` is an assignment.
+pub fn split_assignment(word: &str) -> Option<(&str, &str)> {
+    word.split_once('=').filter(|(name, _)| is_valid_name(name))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn set_export_and_unset() {
+        let mut env = Env::new();
+        env.set("GREETING", "hi");
+        assert_eq!(env.get("GREETING"), Some("hi"));
+        assert!(!env.exported().any(|(name, _)| name == "GREETING"));
+        env.export("GREETING", None);
+        env.set("GREETING", "hello");
+        assert!(env.exported().any(|(name, value)| (name, value) == ("GREETING", "hello")));
+        env.unset("GREETING");
+        assert_eq!(env.get("GREETING"), None);
+    }
+
+    #[test]
+    fn home_is_shortened() {
+        let mut env = Env::new();
+        assert_eq!(env.short_pwd(), "~");
+        env.set("PWD", "/home/guest/docs");
+        assert_eq!(env.short_pwd(), "~/docs");
+        env.set("PWD", "/home/guests");
+        assert_eq!(env.short_pwd(), "/home/guests");
+    }
+
+    #[test]
+    fn assignments() {
+        assert_eq!(split_assignment("PS1=$ "), Some(("PS1", "$ ")));
+        assert_eq!(split_assignment("1X=2"), None);
+        assert_eq!(split_assignment("calc"), None);
+    }
+}
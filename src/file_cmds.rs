@@ -0,0 +1,471 @@
+use crate::args::{Arg, ArgKind, Args, Flag, Spec};
+use crate::cmd::{register_cmd, CmdFuture, Command, Status, EXIT_FAILURE, EXIT_SUCCESS};
+use crate::env::ENV;
+use crate::io::Io;
+use crate::memfs::MemoryFs;
+use crate::rofs::ReadOnlyFs;
+use crate::theme::THEME;
+use crate::vfs::{absolute, format_time, split_parent, FileSystem, Metadata, VfsError, VFS};
+
+// Builtins working on the virtual filesystem.
+pub fn init_file_cmds() {
+    register_cmd(Ls);
+    register_cmd(Cd);
+    register_cmd(Pwd);
+    register_cmd(Mkdir);
+    register_cmd(Rmdir);
+    register_cmd(Rm);
+    register_cmd(Touch);
+    register_cmd(Cat);
+    register_cmd(Cp);
+    register_cmd(Mv);
+    register_cmd(Mount);
+    register_cmd(Umount);
+}
+
+fn report(io: &mut Io, cmd: &str, path: &str, err: VfsError) -> Status {
+    io.error(&format!("{}: {}: {}", cmd, path, err));
+    EXIT_FAILURE
+}
+
+// the last part of a path, for copying or moving into a directory
+fn file_name(path: &str) -> &str {
+    split_parent(path).map(|(_, name)| name).unwrap_or(path)
+}
+
+struct Ls;
+
+impl Command for Ls {
+    fn name(&self) -> &'static str { "ls" }
+    fn summary(&self) -> &'static str { "Lists directory contents." }
+
+    fn spec(&self) -> Spec {
+        Spec::new()
+            .flag(Flag::new("all", "Includes entries starting with a dot.").short('a'))
+            .flag(Flag::new("long", "Shows mode, owner, size and modification time.").short('l'))
+            .arg(Arg::new("path", ArgKind::Path).optional().variadic())
+    }
+
+    fn run<'a>(&'a self, args: Args, io: &'a mut Io) -> CmdFuture<'a> {
+        Box::pin(ls(args, io))
+    }
+}
+
+async fn ls(args: Args, io: &mut Io) -> Status {
+    let mut paths = args.strs("path");
+    if paths.is_empty() {
+        paths.push(".");
+    }
+    let color = io.stdout.is_terminal();
+    let format = |name: &str, meta: &Metadata| {
+        let name = match meta.is_dir && color {
+            true => format!("{}{}{}", THEME.dir, name, THEME.text),
+            false => name.to_string(),
+        };
+        match args.flag("long") {
+            true => format!("{} {:<8} {:>6} {} {}", meta.mode_string(), meta.owner, meta.size, format_time(meta.mtime), name),
+            false => name,
+        }
+    };
+    let separator = if args.flag("long") { "\n" } else { "  " };
+
+    let mut status = EXIT_SUCCESS;
+    let mut sections = Vec::new();
+    for path in &paths {
+        let listing = {
+            let full = absolute(path);
+            let vfs = VFS.lock().unwrap();
+            vfs.metadata(&full).and_then(|meta| match meta.is_dir {
+                true => vfs.read_dir(&full),
+                false => Ok(vec![(path.to_string(), meta)]),
+            })
+        };
+        match listing {
+            Ok(entries) => {
+                let lines: Vec<String> = entries.iter()
+                    .filter(|(name, _)| args.flag("all") || !name.starts_with('.'))
+                    .map(|(name, meta)| format(name, meta))
+                    .collect();
+                let listing = lines.join(separator);
+                sections.push(if paths.len() > 1 { format!("{}:\n{}", path, listing) } else { listing });
+            }
+            Err(err) => status = report(io, "ls", path, err),
+        }
+    }
+    io.print(&sections.join("\n\n"));
+    status
+}
+
+struct Cd;
+
+impl Command for Cd {
+    fn name(&self) -> &'static str { "cd" }
+    fn summary(&self) -> &'static str { "Changes the working directory. 'cd -' goes back to the previous one." }
+
+    fn spec(&self) -> Spec {
+        Spec::new().arg(Arg::new("dir", ArgKind::Path).optional())
+    }
+
+    fn run<'a>(&'a self, args: Args, io: &'a mut Io) -> CmdFuture<'a> {
+        Box::pin(cd(args, io))
+    }
+}
+
+async fn cd(args: Args, io: &mut Io) -> Status {
+    let target = match args.str("dir") {
+        Some("-") => match ENV.lock().unwrap().get("OLDPWD") {
+            Some(old) => old.to_string(),
+            None => {
+                io.error("cd: OLDPWD not set");
+                return EXIT_FAILURE;
+            }
+        },
+        Some(dir) => dir.to_string(),
+        None => "~".to_string(),
+    };
+    let full = absolute(&target);
+    let found = VFS.lock().unwrap().metadata(&full);
+    match found {
+        Ok(meta) if meta.is_dir => {}
+        Ok(_) => return report(io, "cd", &target, VfsError::NotADirectory),
+        Err(err) => return report(io, "cd", &target, err),
+    }
+    if args.str("dir") == Some("-") {
+        io.print(&full);
+    }
+    let mut env = ENV.lock().unwrap();
+    let old = env.get("PWD").unwrap_or("/").to_string();
+    env.set("OLDPWD", &old);
+    env.set("PWD", &full);
+    EXIT_SUCCESS
+}
+
+struct Pwd;
+
+impl Command for Pwd {
+    fn name(&self) -> &'static str { "pwd" }
+    fn summary(&self) -> &'static str { "Prints the working directory." }
+
+    fn run<'a>(&'a self, _args: Args, io: &'a mut Io) -> CmdFuture<'a> {
+        Box::pin(pwd(io))
+    }
+}
+
+async fn pwd(io: &mut Io) -> Status {
+    let pwd = absolute(".");
+    io.print(&pwd);
+    EXIT_SUCCESS
+}
+
+struct Mkdir;
+
+impl Command for Mkdir {
+    fn name(&self) -> &'static str { "mkdir" }
+    fn summary(&self) -> &'static str { "Creates directories." }
+
+    fn spec(&self) -> Spec {
+        Spec::new()
+            .flag(Flag::new("parents", "Creates missing parents too, and is fine if the directory exists.").short('p'))
+            .arg(Arg::new("dir", ArgKind::Path).variadic())
+    }
+
+    fn run<'a>(&'a self, args: Args, io: &'a mut Io) -> CmdFuture<'a> {
+        Box::pin(mkdir(args, io))
+    }
+}
+
+async fn mkdir(args: Args, io: &mut Io) -> Status {
+    let mut status = EXIT_SUCCESS;
+    for dir in args.strs("dir") {
+        let full = absolute(dir);
+        let mut vfs = VFS.lock().unwrap();
+        let result = if args.flag("parents") { vfs.create_dir_all(&full) } else { vfs.create_dir(&full) };
+        drop(vfs);
+        if let Err(err) = result {
+            status = report(io, "mkdir", dir, err);
+        }
+    }
+    status
+}
+
+struct Rmdir;
+
+impl Command for Rmdir {
+    fn name(&self) -> &'static str { "rmdir" }
+    fn summary(&self) -> &'static str { "Removes empty directories." }
+
+    fn spec(&self) -> Spec {
+        Spec::new().arg(Arg::new("dir", ArgKind::Path).variadic())
+    }
+
+    fn run<'a>(&'a self, args: Args, io: &'a mut Io) -> CmdFuture<'a> {
+        Box::pin(rmdir(args, io))
+    }
+}
+
+async fn rmdir(args: Args, io: &mut Io) -> Status {
+    let mut status = EXIT_SUCCESS;
+    for dir in args.strs("dir") {
+        let result = VFS.lock().unwrap().remove_dir(&absolute(dir));
+        if let Err(err) = result {
+            status = report(io, "rmdir", dir, err);
+        }
+    }
+    status
+}
+
+struct Rm;
+
+impl Command for Rm {
+    fn name(&self) -> &'static str { "rm" }
+    fn summary(&self) -> &'static str { "Removes files, and directories with -r." }
+
+    fn spec(&self) -> Spec {
+        Spec::new()
+            .flag(Flag::new("recursive", "Removes directories and everything in them.").short('r'))
+            .flag(Flag::new("force", "Ignores paths that don't exist.").short('f'))
+            .arg(Arg::new("path", ArgKind::Path).variadic())
+    }
+
+    fn run<'a>(&'a self, args: Args, io: &'a mut Io) -> CmdFuture<'a> {
+        Box::pin(rm(args, io))
+    }
+}
+
+async fn rm(args: Args, io: &mut Io) -> Status {
+    let mut status = EXIT_SUCCESS;
+    for path in args.strs("path") {
+        let result = VFS.lock().unwrap().remove(&absolute(path), args.flag("recursive"));
+        match result {
+            Err(VfsError::NotFound) if args.flag("force") => {}
+            Err(err) => status = report(io, "rm", path, err),
+            Ok(()) => {}
+        }
+    }
+    status
+}
+
+struct Touch;
+
+impl Command for Touch {
+    fn name(&self) -> &'static str { "touch" }
+    fn summary(&self) -> &'static str { "Creates empty files or updates their modification time." }
+
+    fn spec(&self) -> Spec {
+        Spec::new().arg(Arg::new("file", ArgKind::Path).variadic())
+    }
+
+    fn run<'a>(&'a self, args: Args, io: &'a mut Io) -> CmdFuture<'a> {
+        Box::pin(touch(args, io))
+    }
+}
+
+async fn touch(args: Args, io: &mut Io) -> Status {
+    let mut status = EXIT_SUCCESS;
+    for file in args.strs("file") {
+        let result = VFS.lock().unwrap().touch(&absolute(file));
+        if let Err(err) = result {
+            status = report(io, "touch", file, err);
+        }
+    }
+    status
+}
+
+struct Cat;
+
+impl Command for Cat {
+    fn name(&self) -> &'static str { "cat" }
+    fn summary(&self) -> &'static str { "Prints files one after another, or what is piped in." }
+
+    fn spec(&self) -> Spec {
+        Spec::new().arg(Arg::new("file", ArgKind::Path).optional().variadic())
+    }
+
+    fn run<'a>(&'a self, args: Args, io: &'a mut Io) -> CmdFuture<'a> {
+        Box::pin(cat(args, io))
+    }
+}
+
+async fn cat(args: Args, io: &mut Io) -> Status {
+    let files = args.strs("file");
+    if files.is_empty() {
+        let input = io.stdin.read_to_string();
+        io.print(&input);
+        return EXIT_SUCCESS;
+    }
+    let mut status = EXIT_SUCCESS;
+    for file in files {
+        let contents = VFS.lock().unwrap().read(&absolute(file));
+        match contents {
+            Ok(contents) => io.print(&String::from_utf8_lossy(&contents)),
+            Err(err) => status = report(io, "cat", file, err),
+        }
+    }
+    status
+}
+
+// Where each source ends up: inside `dest` when it is a directory, otherwise
+// `dest` itself, which only works for a single source.
+fn targets(cmd: &str, sources: &[&str], dest: &str, io: &mut Io) -> Option<Vec<(String, String)>> {
+    let dest_full = absolute(dest);
+    let into_dir = VFS.lock().unwrap().is_dir(&dest_full);
+    if !into_dir && sources.len() > 1 {
+        io.error(&format!("{}: target '{}' is not a directory", cmd, dest));
+        return None;
+    }
+    Some(sources.iter().map(|source| {
+        let from = absolute(source);
+        let to = if into_dir { format!("{}/{}", dest_full.trim_end_matches('/'), file_name(&from)) } else { dest_full.clone() };
+        (from, to)
+    }).collect())
+}
+
+struct Cp;
+
+impl Command for Cp {
+    fn name(&self) -> &'static str { "cp" }
+    fn summary(&self) -> &'static str { "Copies files, and directories with -r." }
+
+    fn spec(&self) -> Spec {
+        Spec::new()
+            .flag(Flag::new("recursive", "Copies directories and everything in them.").short('r'))
+            .arg(Arg::new("source", ArgKind::Path).variadic())
+            .arg(Arg::new("dest", ArgKind::Path))
+    }
+
+    fn run<'a>(&'a self, args: Args, io: &'a mut Io) -> CmdFuture<'a> {
+        Box::pin(cp(args, io))
+    }
+}
+
+async fn cp(args: Args, io: &mut Io) -> Status {
+    let sources = args.strs("source");
+    let Some(targets) = targets("cp", &sources, args.str("dest").unwrap_or_default(), io) else {
+        return EXIT_FAILURE;
+    };
+    let mut status = EXIT_SUCCESS;
+    for (source, (from, to)) in sources.iter().zip(targets) {
+        let result = VFS.lock().unwrap().copy(&from, &to, args.flag("recursive"));
+        if let Err(err) = result {
+            status = report(io, "cp", source, err);
+        }
+    }
+    status
+}
+
+struct Mv;
+
+impl Command for Mv {
+    fn name(&self) -> &'static str { "mv" }
+    fn summary(&self) -> &'static str { "Moves or renames files and directories." }
+
+    fn spec(&self) -> Spec {
+        Spec::new()
+            .arg(Arg::new("source", ArgKind::Path).variadic())
+            .arg(Arg::new("dest", ArgKind::Path))
+    }
+
+    fn run<'a>(&'a self, args: Args, io: &'a mut Io) -> CmdFuture<'a> {
+        Box::pin(mv(args, io))
+    }
+}
+
+async fn mv(args: Args, io: &mut Io) -> Status {
+    let sources = args.strs("source");
+    let Some(targets) = targets("mv", &sources, args.str("dest").unwrap_or_default(), io) else {
+        return EXIT_FAILURE;
+    };
+    let mut status = EXIT_SUCCESS;
+    for (source, (from, to)) in sources.iter().zip(targets) {
+        let result = VFS.lock().unwrap().rename(&from, &to);
+        if let Err(err) = result {
+            status = report(io, "mv", source, err);
+        }
+    }
+    status
+}
+
+struct Mount;
+
+impl Command for Mount {
+    fn name(&self) -> &'static str { "mount" }
+    fn summary(&self) -> &'static str { "Mounts a filesystem over a directory, or lists what is mounted." }
+
+    fn spec(&self) -> Spec {
+        Spec::new()
+            .subcommand("tmpfs", "Mounts an empty filesystem that is gone on reload.", Spec::new().arg(Arg::new("dir", ArgKind::Path)))
+            .subcommand("tar", "Mounts a tar archive read-only.", Spec::new().arg(Arg::new("archive", ArgKind::Path)).arg(Arg::new("dir", ArgKind::Path)))
+            .subcommand("http", "Mounts a directory served over HTTP read-only. It lists its files in index.txt.", Spec::new().arg(Arg::new("url", ArgKind::Text)).arg(Arg::new("dir", ArgKind::Path)))
+    }
+
+    fn run<'a>(&'a self, args: Args, io: &'a mut Io) -> CmdFuture<'a> {
+        Box::pin(mount(args, io))
+    }
+}
+
+async fn mount(args: Args, io: &mut Io) -> Status {
+    let Some(kind) = args.subcommand() else {
+        let lines: Vec<String> = VFS.lock().unwrap().mounts()
+            .map(|mount| format!("{} on {} type {} ({})", mount.source, mount.point, mount.kind, if mount.read_only() { "ro" } else { "rw" }))
+            .collect();
+        io.print(&lines.join("\n"));
+        return EXIT_SUCCESS;
+    };
+    let owner = ENV.lock().unwrap().get("USER").unwrap_or("guest").to_string();
+    let (source, fs): (String, Result<Box<dyn FileSystem>, String>) = match kind {
+        "tar" => {
+            let archive = args.str("archive").unwrap_or_default();
+            let contents = VFS.lock().unwrap().read(&absolute(archive));
+            match contents {
+                Ok(contents) => (absolute(archive), ReadOnlyFs::from_tar(&contents, &owner).map(|fs| Box::new(fs) as Box<dyn FileSystem>)),
+                Err(err) => return report(io, "mount", archive, err),
+            }
+        }
+        "http" => {
+            let url = args.str("url").unwrap_or_default();
+            (url.to_string(), ReadOnlyFs::fetch(url, &owner).await.map(|fs| Box::new(fs) as Box<dyn FileSystem>))
+        }
+        _ => ("tmpfs".to_string(), Ok(Box::new(MemoryFs::new(&owner)) as Box<dyn FileSystem>)),
+    };
+    let fs = match fs {
+        Ok(fs) => fs,
+        Err(err) => {
+            io.error(&format!("mount: {}", err));
+            return EXIT_FAILURE;
+        }
+    };
+    let dir = args.str("dir").unwrap_or_default();
+    let result = VFS.lock().unwrap().mount(&absolute(dir), &source, kind, fs);
+    match result {
+        Ok(()) => EXIT_SUCCESS,
+        Err(err) => report(io, "mount", dir, err),
+    }
+}
+
+struct Umount;
+
+impl Command for Umount {
+    fn name(&self) -> &'static str { "umount" }
+    fn summary(&self) -> &'static str { "Unmounts the filesystem mounted over a directory." }
+
+    fn spec(&self) -> Spec {
+        Spec::new().arg(Arg::new("dir", ArgKind::Path))
+    }
+
+    fn run<'a>(&'a self, args: Args, io: &'a mut Io) -> CmdFuture<'a> {
+        Box::pin(umount(args, io))
+    }
+}
+
+async fn umount(args: Args, io: &mut Io) -> Status {
+    let dir = args.str("dir").unwrap_or_default();
+    let result = VFS.lock().unwrap().unmount(&absolute(dir));
+    match result {
+        Ok(()) => EXIT_SUCCESS,
+        Err(VfsError::InvalidArgument) => {
+            io.error(&format!("umount: {}: not mounted", dir));
+            EXIT_FAILURE
+        }
+        Err(err) => report(io, "umount", dir, err),
+    }
+}
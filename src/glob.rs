@@ -0,0 +1,168 @@
+use crate::vfs::{normalize, FileSystem};
+
+// Whether `pattern` has any unescaped `*`, `?` or `[`.
+pub fn has_wildcards(pattern: &str) -> bool {
+    let mut chars = pattern.chars();
+    while let Some(ch) = chars.next() {
+        match ch {
+            '\\' => {
+                chars.next();
+            }
+            '*' | '?' | '[' => return true,
+            _ => {}
+        }
+    }
+    false
+}
+
+// The paths in `fs` matching `pattern`, sorted and spelled the way the
+// pattern spells them, so relative patterns give relative paths. `*`, `?`
+// and `[...]` match within one path component, `**` on its own matches any
+// number of directories, and a backslash makes the next character literal.
+// Like in bash, names starting with a dot are only matched by a pattern
+// that starts with a dot too.
+// A leading `~` is expanded before the pattern gets here.
+pub fn glob(fs: &dyn FileSystem, cwd: &str, pattern: &str) -> Vec<String> {
+    let (dir, prefix, rest) = match pattern.strip_prefix('/') {
+        Some(rest) => ("/".to_string(), "/".to_string(), rest),
+        None => (cwd.to_string(), String::new(), pattern),
+    };
+    let parts: Vec<&str> = rest.split('/').filter(|part| !part.is_empty()).collect();
+    let mut found = Vec::new();
+    walk(fs, &dir, &prefix, &parts, rest.ends_with('/'), &mut found);
+    found.sort();
+    found.dedup();
+    found
+}
+
+fn walk(fs: &dyn FileSystem, dir: &str, prefix: &str, parts: &[&str], dirs_only: bool, found: &mut Vec<String>) {
+    let Some((part, rest)) = parts.split_first() else {
+        if !prefix.is_empty() && (!dirs_only || fs.is_dir(dir)) {
+            found.push(if dirs_only && !prefix.ends_with('/') { format!("{}/", prefix) } else { prefix.to_string() });
+        }
+        return;
+    };
+    let join = |name: &str| {
+        let path = normalize(dir, "/", name);
+        let spelled = if rest.is_empty() { format!("{}{}", prefix, name) } else { format!("{}{}/", prefix, name) };
+        (path, spelled)
+    };
+
+    if *part == "**" {
+        // none of the directories, then one more for each subdirectory
+        walk(fs, dir, prefix, rest, dirs_only, found);
+        for (name, meta) in fs.read_dir(dir).unwrap_or_default() {
+            if meta.is_dir && !name.starts_with('.') {
+                let path = normalize(dir, "/", &name);
+                walk(fs, &path, &format!("{}{}/", prefix, name), parts, dirs_only, found);
+            }
+        }
+    } else if !has_wildcards(part) {
+        let name = unescape(part);
+        let (path, spelled) = join(&name);
+        if fs.metadata(&path).is_ok() && (rest.is_empty() || fs.is_dir(&path)) {
+            walk(fs, &path, &spelled, rest, dirs_only, found);
+        }
+    } else {
+        for (name, meta) in fs.read_dir(dir).unwrap_or_default() {
+            if (name.starts_with('.') && !part.starts_with('.')) || !matches(part, &name) || (!rest.is_empty() && !meta.is_dir) {
+                continue;
+            }
+            let (path, spelled) = join(&name);
+            walk(fs, &path, &spelled, rest, dirs_only, found);
+        }
+    }
+}
+
+fn unescape(part: &str) -> String {
+    let mut text = String::new();
+    let mut chars = part.chars();
+    while let Some(ch) = chars.next() {
+        text.extend(if ch == '\\' { chars.next() } else { Some(ch) });
+    }
+    text
+}
+
+// Whether `name` matches the single component `pattern`.
+pub fn matches(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+    matches_from(&pattern, &name)
+}
+
+fn matches_from(pattern: &[char], name: &[char]) -> bool {
+    match pattern.first() {
+        None => name.is_empty(),
+        Some('*') => (0..=name.len()).any(|skip| matches_from(&pattern[1..], &name[skip..])),
+        Some('?') => !name.is_empty() && matches_from(&pattern[1..], &name[1..]),
+        Some('[') => match (class(&pattern[1..]), name.first()) {
+            (Some((matched, len)), Some(&ch)) => matched(ch) && matches_from(&pattern[len + 1..], &name[1..]),
+            // an unclosed `[` is just a bracket
+            (None, Some('[')) => matches_from(&pattern[1..], &name[1..]),
+            _ => false,
+        },
+        Some('\\') if pattern.len() > 1 => name.first() == Some(&pattern[1]) && matches_from(&pattern[2..], &name[1..]),
+        Some(ch) => name.first() == Some(ch) && matches_from(&pattern[1..], &name[1..]),
+    }
+}
+
+// The character class after a `[`, like `abc]`, `a-z]` or `!0-9]`: what it
+// matches, and how many characters it takes up including the `]`.
+fn class(pattern: &[char]) -> Option<(impl Fn(char) -> bool + '_, usize)> {
+    let negated = matches!(pattern.first(), Some('!' | '^'));
+    let start = usize::from(negated);
+    // a `]` straight after the `[` is part of the class
+    let end = start + 1 + pattern.get(start + 1..)?.iter().position(|&ch| ch == ']')?;
+    let members = &pattern[start..end];
+    let matched = move |ch: char| {
+        let mut i = 0;
+        let mut found = false;
+        while i < members.len() {
+            if i + 2 < members.len() && members[i + 1] == '-' {
+                found |= (members[i]..=members[i + 2]).contains(&ch);
+                i += 3;
+            } else {
+                found |= members[i] == ch;
+                i += 1;
+            }
+        }
+        found != negated
+    };
+    Some((matched, end + 1))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memfs::MemoryFs;
+
+    #[test]
+    fn patterns() {
+        assert!(matches("*.txt", "notes.txt"));
+        assert!(!matches("*.txt", "notes.txt.bak"));
+        assert!(matches("a?c", "abc") && !matches("a?c", "ac"));
+        assert!(matches("[abc]x", "bx") && !matches("[abc]x", "dx"));
+        assert!(matches("[a-c][!0-9]", "bz") && !matches("[a-c][!0-9]", "b1"));
+        assert!(matches("[]]", "]"));
+        assert!(matches("\\*", "*") && !matches("\\*", "a"));
+        assert!(matches("[x", "[x"));
+        assert!(has_wildcards("a*") && !has_wildcards("a\\*b"));
+    }
+
+    #[test]
+    fn globbing_the_tree() {
+        let mut fs = MemoryFs::new("guest");
+        fs.create_dir_all("/home/guest/docs/old").unwrap();
+        for file in ["/home/guest/b.txt", "/home/guest/a.txt", "/home/guest/.hidden.txt", "/home/guest/docs/c.txt", "/home/guest/docs/old/d.txt", "/home/guest/docs/e.md"] {
+            fs.write(file, b"", false).unwrap();
+        }
+        let glob = |pattern: &str| glob(&fs, "/home/guest", pattern);
+        assert_eq!(glob("*.txt"), vec!["a.txt", "b.txt"]);
+        assert_eq!(glob(".*.txt"), vec![".hidden.txt"]);
+        assert_eq!(glob("docs/*"), vec!["docs/c.txt", "docs/e.md", "docs/old"]);
+        assert_eq!(glob("*/"), vec!["docs/"]);
+        assert_eq!(glob("**/*.txt"), vec!["a.txt", "b.txt", "docs/c.txt", "docs/old/d.txt"]);
+        assert_eq!(glob("/home/*/docs/?.md"), vec!["/home/guest/docs/e.md"]);
+        assert_eq!(glob("*.png"), Vec::<String>::new());
+    }
+}
@@ -0,0 +1,206 @@
+use crate::storage::{default_storage, Storage};
+use once_cell::sync::Lazy;
+use std::sync::Mutex;
+
+const HISTORY_KEY: &str = "buudunn.history";
+const HISTORY_LIMIT: usize = 1000;
+
+pub static HISTORY: Lazy<Mutex<History>> = Lazy::new(|| Mutex::new(History::new(default_storage())));
+
+// Every submitted command, oldest first, plus the Up/Down navigation state.
+pub struct History {
+    entries: Vec<String>,
+    // index of the entry recalled with Up/Down, if any
+    position: Option<usize>,
+    // what was typed before navigation started, restored by Down
+    draft: String,
+    storage: Box<dyn Storage>,
+}
+
+impl History {
+    pub fn new(storage: Box<dyn Storage>) -> History {
+        let entries = storage
+            .get(HISTORY_KEY)
+            .map(|saved| saved.lines().map(decode).collect())
+            .unwrap_or_default();
+        History { entries, position: None, draft: String::new(), storage }
+    }
+
+    pub fn entries(&self) -> &[String] {
+        &self.entries
+    }
+
+    // entries are numbered from 1, like in bash
+    pub fn get(&self, number: usize) -> Option<&String> {
+        self.entries.get(number.checked_sub(1)?)
+    }
+
+    pub fn push(&mut self, cmd: &str) {
+        self.position = None;
+        if cmd.trim().is_empty() || self.entries.last().map(String::as_str) == Some(cmd) {
+            return;
+        }
+        self.entries.push(cmd.to_string());
+        if self.entries.len() > HISTORY_LIMIT {
+            self.entries.remove(0);
+        }
+        self.save();
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.position = None;
+        self.storage.remove(HISTORY_KEY);
+    }
+
+    fn save(&mut self) {
+        let saved: Vec<String> = self.entries.iter().map(|entry| encode(entry)).collect();
+        self.storage.set(HISTORY_KEY, &saved.join("\n"));
+    }
+
+    // Up: the entry before the one shown, remembering `current` as the draft
+    pub fn previous(&mut self, current: &str) -> Option<String> {
+        let position = match self.position {
+            None if self.entries.is_empty() => return None,
+            None => {
+                self.draft = current.to_string();
+                self.entries.len() - 1
+            }
+            Some(0) => return None,
+            Some(position) => position - 1,
+        };
+        self.position = Some(position);
+        Some(self.entries[position].clone())
+    }
+
+    // Down: the entry after the one shown, then back to the draft
+    pub fn next(&mut self) -> Option<String> {
+        let position = self.position?;
+        if position + 1 < self.entries.len() {
+            self.position = Some(position + 1);
+            Some(self.entries[position + 1].clone())
+        } else {
+            self.position = None;
+            Some(std::mem::take(&mut self.draft))
+        }
+    }
+
+    // Replaces `!!`, `!n` and `!-n` outside single quotes with the matching
+    // history entries.
+    pub fn expand(&self, line: &str) -> Result<String, String> {
+        let chars: Vec<char> = line.chars().collect();
+        let mut output = String::new();
+        let mut in_single_quotes = false;
+        let mut i = 0;
+        while i < chars.len() {
+            let ch = chars[i];
+            if ch == '\'' {
+                in_single_quotes = !in_single_quotes;
+            }
+            if ch != '!' || in_single_quotes {
+                if ch == '\\' && !in_single_quotes && i + 1 < chars.len() {
+                    output.push(ch);
+                    i += 1;
+                    output.push(chars[i]);
+                } else {
+                    output.push(ch);
+                }
+                i += 1;
+                continue;
+            }
+            let event: String = if chars.get(i + 1) == Some(&'!') {
+                "!".to_string()
+            } else {
+                let sign = usize::from(chars.get(i + 1) == Some(&'-'));
+                let digits = chars[i + 1 + sign..].iter().take_while(|c| c.is_ascii_digit()).count();
+                if digits == 0 {
+                    output.push(ch);
+                    i += 1;
+                    continue;
+                }
+                chars[i + 1..i + 1 + sign + digits].iter().collect()
+            };
+            let entry = match event.as_str() {
+                "!" => self.entries.last(),
+                number => match number.strip_prefix('-') {
+                    Some(back) => back
+                        .parse::<usize>()
+                        .ok()
+                        .and_then(|back| self.entries.len().checked_sub(back))
+                        .and_then(|index| self.entries.get(index)),
+                    None => number.parse().ok().and_then(|number| self.get(number)),
+                },
+            };
+            match entry {
+                Some(entry) => output.push_str(entry),
+                None => return Err(format!("!{}: event not found", event)),
+            }
+            i += 1 + event.chars().count();
+        }
+        Ok(output)
+    }
+}
+
+fn encode(entry: &str) -> String {
+    entry.replace('\\', "\\\\").replace('\n', "\\n")
+}
+
+fn decode(saved: &str) -> String {
+    let mut output = String::new();
+    let mut chars = saved.chars();
+    while let Some(ch) = chars.next() {
+        if ch == '\\' {
+            match chars.next() {
+                Some('n') => output.push('\n'),
+                Some(other) => output.push(other),
+                None => output.push('\\'),
+            }
+        } else {
+            output.push(ch);
+        }
+    }
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::MemoryStorage;
+
+    fn history(entries: &[&str]) -> History {
+        let mut history = History::new(Box::new(MemoryStorage::default()));
+        for entry in entries {
+            history.push(entry);
+        }
+        history
+    }
+
+    #[test]
+    fn navigation_restores_the_draft() {
+        let mut history = history(&["help", "calc add 1 2"]);
+        assert_eq!(history.previous("ec").as_deref(), Some("calc add 1 2"));
+        assert_eq!(history.previous("").as_deref(), Some("help"));
+        assert_eq!(history.previous(""), None);
+        assert_eq!(history.next().as_deref(), Some("calc add 1 2"));
+        assert_eq!(history.next().as_deref(), Some("ec"));
+        assert_eq!(history.next(), None);
+    }
+
+    #[test]
+    fn bang_expansion() {
+        let history = history(&["help", "echo hi"]);
+        assert_eq!(history.expand("!!").unwrap(), "echo hi");
+        assert_eq!(history.expand("!1 calc").unwrap(), "help calc");
+        assert_eq!(history.expand("!-2").unwrap(), "help");
+        assert_eq!(history.expand("echo '!!' !").unwrap(), "echo '!!' !");
+        assert_eq!(history.expand("!9"), Err("!9: event not found".to_string()));
+    }
+
+    #[test]
+    fn entries_survive_a_reload() {
+        let mut storage = MemoryStorage::default();
+        storage.set(HISTORY_KEY, &encode("echo \"a\nb\\c\""));
+        let history = History::new(Box::new(storage));
+        assert_eq!(history.entries(), ["echo \"a\nb\\c\""]);
+    }
+}
@@ -0,0 +1,176 @@
+use crate::theme::THEME;
+use crate::utils::{draw_text, start_new_line};
+use std::{cell::RefCell, rc::Rc};
+use web_sys::CanvasRenderingContext2d;
+
+// Somewhere a command's output can go.
+pub trait Sink {
+    fn write(&mut self, text: &str);
+
+    // whether the text ends up on screen, where colour markup makes sense
+    fn is_terminal(&self) -> bool {
+        false
+    }
+
+    // Ends the line if something is on it, so the next command's output
+    // starts on a fresh one.
+    fn end_line(&mut self) {}
+
+    // another handle writing to the same place
+    fn share(&self) -> Box<dyn Sink>;
+}
+
+// Renders a stream on the terminal. Error streams are drawn in the theme's
+// error style.
+pub struct Terminal {
+    context: CanvasRenderingContext2d,
+    style: &'static str,
+}
+
+impl Terminal {
+    pub fn stdout(context: &CanvasRenderingContext2d) -> Terminal {
+        Terminal { context: context.clone(), style: "" }
+    }
+
+    pub fn stderr(context: &CanvasRenderingContext2d) -> Terminal {
+        Terminal { context: context.clone(), style: THEME.error }
+    }
+}
+
+impl Sink for Terminal {
+    fn write(&mut self, text: &str) {
+        draw_text(&format!("{}{}", self.style, text), &self.context);
+    }
+
+    fn is_terminal(&self) -> bool {
+        true
+    }
+
+    fn end_line(&mut self) {
+        start_new_line(&self.context);
+    }
+
+    fn share(&self) -> Box<dyn Sink> {
+        Box::new(Terminal { context: self.context.clone(), style: self.style })
+    }
+}
+
+// Keeps whatever is written to it. Clones share the same text, so one handle
+// can be given to a command and the other read afterwards.
+#[derive(Clone, Default)]
+pub struct Buffer(Rc<RefCell<String>>);
+
+impl Buffer {
+    pub fn new() -> Buffer {
+        Buffer::default()
+    }
+
+    pub fn take(&self) -> String {
+        self.0.take()
+    }
+}
+
+impl Sink for Buffer {
+    fn write(&mut self, text: &str) {
+        self.0.borrow_mut().push_str(text);
+    }
+
+    fn end_line(&mut self) {
+        let mut text = self.0.borrow_mut();
+        if !text.is_empty() && !text.ends_with('\n') {
+            text.push('\n');
+        }
+    }
+
+    fn share(&self) -> Box<dyn Sink> {
+        Box::new(self.clone())
+    }
+}
+
+// Input handed to a command. Interactive commands read nothing; text only
+// arrives here when something feeds it in. Clones share what is left, so
+// the commands of a script read one after another from the same input.
+#[derive(Clone, Default)]
+pub struct Stdin {
+    text: Rc<RefCell<Option<String>>>,
+}
+
+impl Stdin {
+    pub fn from_text(text: &str) -> Stdin {
+        Stdin { text: Rc::new(RefCell::new(Some(text.to_string()))) }
+    }
+
+    pub fn is_terminal(&self) -> bool {
+        self.text.borrow().is_none()
+    }
+
+    // everything left on the stream
+    pub fn read_to_string(&mut self) -> String {
+        self.text.borrow_mut().as_mut().map(std::mem::take).unwrap_or_default()
+    }
+}
+
+// The streams a command runs with.
+pub struct Io {
+    pub stdin: Stdin,
+    pub stdout: Box<dyn Sink>,
+    pub stderr: Box<dyn Sink>,
+}
+
+impl Io {
+    pub fn terminal(context: &CanvasRenderingContext2d) -> Io {
+        Io { stdin: Stdin::default(), stdout: Box::new(Terminal::stdout(context)), stderr: Box::new(Terminal::stderr(context)) }
+    }
+
+    // The same streams again, for running the commands of a script or a
+    // loop one after another.
+    pub fn share(&self) -> Io {
+        Io { stdin: self.stdin.clone(), stdout: self.stdout.share(), stderr: self.stderr.share() }
+    }
+
+    pub fn print(&mut self, text: &str) {
+        self.stdout.write(text);
+    }
+
+    // Errors always end their line, so callers never add a newline and
+    // several in a row each get their own.
+    pub fn error(&mut self, text: &str) {
+        self.stderr.write(text);
+        self.stderr.end_line();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn buffers_share_what_was_written() {
+        let stdout = Buffer::new();
+        let stderr = Buffer::new();
+        let mut io = Io { stdin: Stdin::from_text("1+2"), stdout: Box::new(stdout.clone()), stderr: Box::new(stderr.clone()) };
+        assert!(!io.stdin.is_terminal());
+        let input = io.stdin.read_to_string();
+        io.print(&input);
+        io.print("\n");
+        io.error("oops");
+        assert_eq!(stdout.take(), "1+2\n");
+        assert_eq!(stderr.take(), "oops\n");
+        assert_eq!(io.stdin.read_to_string(), "");
+    }
+
+    #[test]
+    fn shared_streams_take_turns() {
+        let stdout = Buffer::new();
+        let mut io = Io { stdin: Stdin::from_text("abc"), stdout: Box::new(stdout.clone()), stderr: Box::new(Buffer::new()) };
+        let mut first = io.share();
+        first.print("one");
+        assert_eq!(first.stdin.read_to_string(), "abc");
+        io.stdout.end_line();
+        io.stdout.end_line();
+        io.share().print("two");
+        assert_eq!(io.stdin.read_to_string(), "");
+        assert!(!io.stdin.is_terminal());
+        assert_eq!(stdout.take(), "one\ntwo");
+    }
+}
@@ -0,0 +1,384 @@
+use std::fmt;
+
+// A problem with a command line, at a byte offset into it.
+#[derive(Clone, PartialEq, Debug)]
+pub struct ParseError {
+    pub message: String,
+    pub pos: usize,
+}
+
+impl ParseError {
+    pub fn new(message: impl Into<String>, pos: usize) -> ParseError {
+        ParseError { message: message.into(), pos }
+    }
+
+    // The line of `source` the error is on, with a caret under the spot.
+    pub fn caret(&self, source: &str) -> String {
+        let pos = self.pos.min(source.len());
+        let start = source[..pos].rfind('\n').map(|i| i + 1).unwrap_or(0);
+        let end = source[pos..].find('\n').map(|i| pos + i).unwrap_or(source.len());
+        format!("{}\n{}^", &source[start..end], " ".repeat(source[start..pos].chars().count()))
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+// One piece of a word. A word like `"$HOME"/*.txt` is a quoted variable
+// followed by unquoted text.
+#[derive(Clone, PartialEq, Debug)]
+pub enum WordPart {
+    // unquoted text, where wildcards mean something
+    Literal(String),
+    // quoted or escaped text, taken as it is
+    Quoted(String),
+    // $NAME, ${NAME}, and the special $?, $#, $@, $* and $0 to $9
+    Var { name: String, quoted: bool },
+    // $(...) or `...`, holding the command line inside
+    Command { source: String, quoted: bool },
+    // $((...)), holding the expression inside
+    Arithmetic { expr: String, quoted: bool },
+}
+
+#[derive(Clone, PartialEq, Debug, Default)]
+pub struct Word {
+    pub parts: Vec<WordPart>,
+    // where the word starts in the source
+    pub pos: usize,
+}
+
+impl Word {
+    // the word's text if it is nothing but unquoted text, as keywords and
+    // assignments have to be
+    pub fn literal(&self) -> Option<&str> {
+        match self.parts.as_slice() {
+            [WordPart::Literal(text)] => Some(text),
+            _ => None,
+        }
+    }
+
+    fn push(&mut self, quoted: bool, ch: char) {
+        match (self.parts.last_mut(), quoted) {
+            (Some(WordPart::Literal(text)), false) | (Some(WordPart::Quoted(text)), true) => text.push(ch),
+            (_, false) => self.parts.push(WordPart::Literal(ch.to_string())),
+            (_, true) => self.parts.push(WordPart::Quoted(ch.to_string())),
+        }
+    }
+}
+
+// Which way a redirect points its stream.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Direction {
+    // `<`
+    Read,
+    // `>`, replacing the file
+    Write,
+    // `>>`, adding to the end of the file
+    Append,
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub enum Token {
+    Word(Word),
+    // `;`, `;;`, `&&`, `||`, `|`, `&`, `(`, `)` and newlines
+    Operator(&'static str),
+    // `<`, `>` or `>>`, with the number of the stream it is for: 0, 1 or 2
+    Redirect { fd: u8, direction: Direction },
+}
+
+impl Token {
+    // how the token reads in an error message
+    pub fn describe(&self) -> String {
+        match self {
+            Token::Word(word) => word.literal().unwrap_or("word").to_string(),
+            Token::Operator("\n") => "newline".to_string(),
+            Token::Operator(operator) => operator.to_string(),
+            Token::Redirect { direction: Direction::Read, .. } => "<".to_string(),
+            Token::Redirect { direction: Direction::Append, .. } => ">>".to_string(),
+            Token::Redirect { direction: Direction::Write, .. } => ">".to_string(),
+        }
+    }
+}
+
+const OPERATORS: [&str; 9] = [";;", "&&", "||", ";", "&", "|", "(", ")", "\n"];
+
+// Splits a command line into tokens, each with the byte offset it starts at.
+// Quotes and escapes are resolved here; `#` starts a comment running to the
+// end of the line, and a backslash before a newline joins the two lines.
+pub fn tokenize(source: &str) -> Result<Vec<(Token, usize)>, ParseError> {
+    let mut lexer = Lexer { source, pos: 0 };
+    let mut tokens = Vec::new();
+    loop {
+        while lexer.eat(" ") || lexer.eat("\t") || lexer.eat("\\\n") {}
+        let start = lexer.pos;
+        let Some(ch) = lexer.peek() else { break };
+        if ch == '#' {
+            lexer.pos += lexer.rest().find('\n').unwrap_or(lexer.rest().len());
+        } else if let Some(operator) = OPERATORS.iter().find(|operator| lexer.rest().starts_with(**operator)) {
+            lexer.pos += operator.len();
+            tokens.push((Token::Operator(operator), start));
+        } else if let Some((fd, direction, len)) = lexer.redirect() {
+            // only stdin, stdout and stderr exist
+            if fd > 2 {
+                return Err(ParseError::new(format!("{}: bad file descriptor", fd), start));
+            }
+            lexer.pos += len;
+            tokens.push((Token::Redirect { fd, direction }, start));
+        } else {
+            tokens.push((Token::Word(lexer.word()?), start));
+        }
+    }
+    Ok(tokens)
+}
+
+struct Lexer<'a> {
+    source: &'a str,
+    pos: usize,
+}
+
+impl<'a> Lexer<'a> {
+    fn rest(&self) -> &'a str {
+        &self.source[self.pos..]
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.rest().chars().next()
+    }
+
+    fn next(&mut self) -> Option<char> {
+        let ch = self.peek()?;
+        self.pos += ch.len_utf8();
+        Some(ch)
+    }
+
+    fn eat(&mut self, text: &str) -> bool {
+        let found = self.rest().starts_with(text);
+        if found {
+            self.pos += text.len();
+        }
+        found
+    }
+
+    // `<`, `>`, `>>`, or one of those after a stream number like `2>`
+    fn redirect(&self) -> Option<(u8, Direction, usize)> {
+        let rest = self.rest();
+        let (fd, after) = match rest.chars().next()? {
+            digit @ '0'..='9' => (Some(digit as u8 - b'0'), &rest[1..]),
+            _ => (None, rest),
+        };
+        let len = rest.len() - after.len();
+        if after.starts_with(">>") {
+            Some((fd.unwrap_or(1), Direction::Append, len + 2))
+        } else if after.starts_with('>') {
+            Some((fd.unwrap_or(1), Direction::Write, len + 1))
+        } else if after.starts_with('<') {
+            Some((fd.unwrap_or(0), Direction::Read, len + 1))
+        } else {
+            None
+        }
+    }
+
+    fn word(&mut self) -> Result<Word, ParseError> {
+        let mut word = Word { parts: Vec::new(), pos: self.pos };
+        while let Some(ch) = self.peek() {
+            if ch.is_whitespace() || ";&|()<>".contains(ch) {
+                break;
+            }
+            let start = self.pos;
+            self.next();
+            match ch {
+                '\\' => match self.next() {
+                    Some('\n') => {}
+                    Some(escaped) => word.push(true, escaped),
+                    None => word.push(false, '\\'),
+                },
+                '\'' => {
+                    let Some(end) = self.rest().find('\'') else {
+                        return Err(ParseError::new("unterminated single quote", start));
+                    };
+                    word.parts.push(WordPart::Quoted(self.rest()[..end].to_string()));
+                    self.pos += end + 1;
+                }
+                '"' => self.double_quoted(&mut word, start)?,
+                '$' => self.dollar(&mut word, false, start)?,
+                '`' => self.backticks(&mut word, false, start)?,
+                _ => word.push(false, ch),
+            }
+        }
+        Ok(word)
+    }
+
+    // the inside of "...", where only `$`, backticks and backslashes before
+    // `$`, `` ` ``, `"`, `\` or a newline mean anything
+    fn double_quoted(&mut self, word: &mut Word, start: usize) -> Result<(), ParseError> {
+        word.parts.push(WordPart::Quoted(String::new()));
+        loop {
+            let pos = self.pos;
+            match self.next() {
+                None => return Err(ParseError::new("unterminated double quote", start)),
+                Some('"') => return Ok(()),
+                Some('\\') => match self.peek() {
+                    Some('\n') => {
+                        self.next();
+                    }
+                    Some(escaped @ ('$' | '`' | '"' | '\\')) => {
+                        self.next();
+                        word.push(true, escaped);
+                    }
+                    _ => word.push(true, '\\'),
+                },
+                Some('$') => self.dollar(word, true, pos)?,
+                Some('`') => self.backticks(word, true, pos)?,
+                Some(ch) => word.push(true, ch),
+            }
+        }
+    }
+
+    // what follows a `$`: a variable, $(...), $((...)), or just a dollar sign
+    fn dollar(&mut self, word: &mut Word, quoted: bool, start: usize) -> Result<(), ParseError> {
+        if self.eat("((") {
+            let end = self.closing_parens(2).ok_or_else(|| ParseError::new("unterminated arithmetic expansion", start))?;
+            word.parts.push(WordPart::Arithmetic { expr: self.rest()[..end].to_string(), quoted });
+            self.pos += end + 2;
+        } else if self.eat("(") {
+            let end = self.closing_parens(1).ok_or_else(|| ParseError::new("unterminated command substitution", start))?;
+            word.parts.push(WordPart::Command { source: self.rest()[..end].to_string(), quoted });
+            self.pos += end + 1;
+        } else if self.eat("{") {
+            let end = self.rest().find('}').ok_or_else(|| ParseError::new("missing closing `}'", start))?;
+            let name = &self.rest()[..end];
+            if !is_var_name(name) {
+                return Err(ParseError::new(format!("${{{}}}: bad substitution", name), start));
+            }
+            word.parts.push(WordPart::Var { name: name.to_string(), quoted });
+            self.pos += end + 1;
+        } else {
+            let rest = self.rest();
+            let len = match rest.chars().next() {
+                Some('?' | '#' | '@' | '*' | '0'..='9') => 1,
+                Some(ch) if ch.is_ascii_alphabetic() || ch == '_' => rest.find(|ch: char| !(ch.is_ascii_alphanumeric() || ch == '_')).unwrap_or(rest.len()),
+                _ => 0,
+            };
+            match len {
+                0 => word.push(quoted, '$'),
+                _ => word.parts.push(WordPart::Var { name: rest[..len].to_string(), quoted }),
+            }
+            self.pos += len;
+        }
+        Ok(())
+    }
+
+    // the offset in `rest` of the `)` (or `))`) closing a substitution,
+    // skipping over nested parentheses and anything quoted
+    fn closing_parens(&self, count: usize) -> Option<usize> {
+        let rest = self.rest();
+        let mut depth = 0;
+        let mut chars = rest.char_indices();
+        while let Some((i, ch)) = chars.next() {
+            match ch {
+                '\\' => {
+                    chars.next();
+                }
+                '\'' | '"' => {
+                    chars.find(|(_, close)| *close == ch)?;
+                }
+                '(' => depth += 1,
+                ')' if depth == 0 => return rest[i..].starts_with(&")".repeat(count)).then_some(i),
+                ')' => depth -= 1,
+                _ => {}
+            }
+        }
+        None
+    }
+
+    // `...`, where a backslash before `` ` ``, `$` or `\` stands for it
+    fn backticks(&mut self, word: &mut Word, quoted: bool, start: usize) -> Result<(), ParseError> {
+        let mut source = String::new();
+        loop {
+            match self.next() {
+                None => return Err(ParseError::new("unterminated command substitution", start)),
+                Some('`') => break,
+                Some('\\') if matches!(self.peek(), Some('`' | '$' | '\\')) => source.extend(self.next()),
+                Some(ch) => source.push(ch),
+            }
+        }
+        word.parts.push(WordPart::Command { source, quoted });
+        Ok(())
+    }
+}
+
+fn is_var_name(name: &str) -> bool {
+    matches!(name, "?" | "#" | "@" | "*") || (!name.is_empty() && name.chars().all(|ch| ch.is_ascii_digit())) || crate::env::is_valid_name(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn words(source: &str) -> Vec<Vec<WordPart>> {
+        tokenize(source).unwrap().into_iter().filter_map(|(token, _)| match token {
+            Token::Word(word) => Some(word.parts),
+            _ => None,
+        }).collect()
+    }
+
+    fn literal(text: &str) -> WordPart {
+        WordPart::Literal(text.to_string())
+    }
+
+    fn quoted(text: &str) -> WordPart {
+        WordPart::Quoted(text.to_string())
+    }
+
+    #[test]
+    fn quotes_and_escapes() {
+        assert_eq!(words(r#"echo 'a "b"' "c 'd' \"\$\q" e\ f *"#), vec![
+            vec![literal("echo")],
+            vec![quoted("a \"b\"")],
+            vec![quoted("c 'd' \"$\\q")],
+            vec![literal("e"), quoted(" "), literal("f")],
+            vec![literal("*")],
+        ]);
+        assert_eq!(words("echo '' \"\""), vec![vec![literal("echo")], vec![quoted("")], vec![quoted("")]]);
+    }
+
+    #[test]
+    fn operators_redirects_and_comments() {
+        let tokens: Vec<Token> = tokenize("a&&b||c;d|e 2>>f >g <h # not;this\nx\\\ny").unwrap().into_iter().map(|(token, _)| token).collect();
+        let described: Vec<String> = tokens.iter().map(Token::describe).collect();
+        assert_eq!(described, vec!["a", "&&", "b", "||", "c", ";", "d", "|", "e", ">>", "f", ">", "g", "<", "h", "newline", "xy"]);
+        assert_eq!(tokens[9], Token::Redirect { fd: 2, direction: Direction::Append });
+        assert_eq!(tokenize("cat 2<f 0>g").unwrap().into_iter().filter(|(token, _)| matches!(token, Token::Redirect { .. })).map(|(token, _)| token).collect::<Vec<_>>(), vec![
+            Token::Redirect { fd: 2, direction: Direction::Read },
+            Token::Redirect { fd: 0, direction: Direction::Write },
+        ]);
+        assert_eq!(words("a#b x2>y"), vec![vec![literal("a#b")], vec![literal("x2")], vec![literal("y")]]);
+    }
+
+    #[test]
+    fn substitution_markers() {
+        assert_eq!(words("$? ${HOME}/x \"$USER\" $ `ls \\`pwd\\`` $(echo \")\" $(pwd)) $((1 + (2 * 3)))"), vec![
+            vec![WordPart::Var { name: "?".to_string(), quoted: false }],
+            vec![WordPart::Var { name: "HOME".to_string(), quoted: false }, literal("/x")],
+            vec![quoted(""), WordPart::Var { name: "USER".to_string(), quoted: true }],
+            vec![literal("$")],
+            vec![WordPart::Command { source: "ls `pwd`".to_string(), quoted: false }],
+            vec![WordPart::Command { source: "echo \")\" $(pwd)".to_string(), quoted: false }],
+            vec![WordPart::Arithmetic { expr: "1 + (2 * 3)".to_string(), quoted: false }],
+        ]);
+    }
+
+    #[test]
+    fn errors_point_at_the_problem() {
+        let error = tokenize("echo \"abc").unwrap_err();
+        assert_eq!(error, ParseError::new("unterminated double quote", 5));
+        assert_eq!(error.caret("echo \"abc"), "echo \"abc\n     ^");
+        assert_eq!(tokenize("ls\necho 'x").unwrap_err().caret("ls\necho 'x"), "echo 'x\n     ^");
+        assert_eq!(tokenize("echo $(pwd").unwrap_err().pos, 5);
+        assert_eq!(tokenize("echo ${A-B}").unwrap_err().message, "${A-B}: bad substitution");
+        assert_eq!(tokenize("echo hi 3>f").unwrap_err(), ParseError::new("3: bad file descriptor", 8));
+    }
+}
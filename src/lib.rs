@@ -1,10 +1,33 @@
 mod utils;
 mod cmd;
+mod screen;
+mod render;
+mod vt;
+mod editor;
+mod storage;
+mod history;
+mod args;
+mod io;
+mod theme;
+mod glob;
+mod lexer;
+mod parser;
+mod shell;
+mod env;
+mod prompt;
+mod vfs;
+mod memfs;
+mod rofs;
+mod file_cmds;
+mod script;
 use utils::*;
 use cmd::*;
+use history::HISTORY;
+use theme::THEME;
+use prompt::prompt;
+use vfs::load_vfs;
 use std::f64;
 use wasm_bindgen::prelude::*;
-use console_error_panic_hook;
 use std::panic;
 use wasm_bindgen::JsValue;
 
@@ -26,6 +49,7 @@ extern "C" {
     fn log_many(a: &str, b: &str);
 }
 
+#[allow(unused_macros)]
 macro_rules! console_log {
     // Note that this is using the `log` function imported above during
     // `bare_bones`
@@ -60,6 +84,7 @@ async fn start() {
     context.set_stroke_style(&JsValue::from_str("#FFFFFF"));
     context.set_font("14px Gohu");
     init_cmd();
+    let warning = load_vfs();
 
     let welcome_text = 
 r#" ____                  _                   
@@ -74,12 +99,11 @@ To learn more, type \#FFFF00'help about'\#FFFFFF. To get a list of commands, typ
 
 "#;
 
-    let user = USER.lock().unwrap().clone();
-    let host = HOST.lock().unwrap().clone();
-    let cwd = CWD.lock().unwrap().clone();
     draw_text(welcome_text, &context);
-    draw_text(&format!("\\#90EE90{}@{}: \\#FFFFFF\\#ADD8E6{}\\#FFFFFF \\#FFFF00$ \\#FFFFFF", user, host, cwd), &context);
-    lock_cursor_here();
+    if let Some(warning) = warning {
+        draw_text(&format!("{}{}{}\n\n", THEME.error, warning, THEME.text), &context);
+    }
+    draw_prompt(&prompt(), &context);
     unlock_input();
 }
 
@@ -89,29 +113,82 @@ pub async fn keydownhandler(event: web_sys::KeyboardEvent, context: &web_sys::Ca
 
             let key = event.key();
 
+            if event.shift_key() && (key == "PageUp" || key == "PageDown") {
+                scroll_page(if key == "PageUp" { 1 } else { -1 }, context);
+                return;
+            }
+
             if !get_is_input_locked() {
-            if key.len() == 1 && !event.ctrl_key() && !event.alt_key() && !event.meta_key() {
-                draw_text(&key, &context);
-                add_to_cmd_bank(&key);
+            if is_searching_history() && !history_search_key(&key, event.ctrl_key(), context) {
+                return;
+            }
+
+            if event.ctrl_key() && key.eq_ignore_ascii_case("r") {
+                start_history_search(context);
+            } else if key.len() == 1 && !event.ctrl_key() && !event.alt_key() && !event.meta_key() {
+                edit_cmd_bank(context, |cmd_bank| cmd_bank.insert(&key));
             } else if key == "Enter"  {
                 if event.shift_key() {
-                    draw_text("\n", &context);
-                    add_to_cmd_bank("\n");
+                    edit_cmd_bank(context, |cmd_bank| cmd_bank.insert("\n"));
                 } else {
-                    let cmd = &get_cmd_bank();
+                    // output starts after the whole command, not at the cursor
+                    edit_cmd_bank(context, |cmd_bank| cmd_bank.end());
+                    let typed = get_cmd_bank();
                     clear_cmd_bank();
-                    let _ = pass_cmd(cmd, &context).await;
-                    draw_text("\n", &context);
-                    let user = USER.lock().unwrap().clone();
-                    let host = HOST.lock().unwrap().clone();
-                    let cwd = CWD.lock().unwrap().clone();
-                    draw_text(&format!("\\#90EE90{}@{}: \\#FFFFFF\\#ADD8E6{}\\#FFFFFF \\#FFFF00$ \\#FFFFFF", user, host, cwd), &context);
-                    lock_cursor_here();
+                    let expanded = HISTORY.lock().unwrap().expand(&typed);
+                    match expanded {
+                        Ok(cmd) => {
+                            if cmd != typed {
+                                // show what the history expansion actually runs
+                                draw_text(&format!("\n{}", cmd), context);
+                            }
+                            HISTORY.lock().unwrap().push(&cmd);
+                            let _ = pass_cmd(&cmd, context).await;
+                        },
+                        Err(err) => draw_text(&format!("\n{}{}", THEME.error, err), context),
+                    }
+                    start_new_line(context);
+                    draw_prompt(&prompt(), context);
                     unlock_input();
                 }
-            } else if key == "Backspace" {
-                backspace(&context);
-                remove_last_from_cmd_bank();
+            } else if key == "Tab" {
+                complete_cmd_bank(context);
+            } else if key == "ArrowUp" || key == "ArrowDown" {
+                let entry = if key == "ArrowUp" {
+                    HISTORY.lock().unwrap().previous(&get_cmd_bank())
+                } else {
+                    HISTORY.lock().unwrap().next()
+                };
+                if let Some(entry) = entry {
+                    edit_cmd_bank(context, |cmd_bank| cmd_bank.set_text(&entry));
+                }
+            } else {
+                let ctrl = event.ctrl_key();
+                edit_cmd_bank(context, |cmd_bank| match key.as_str() {
+                    "Backspace" => cmd_bank.backspace(),
+                    "Delete" => cmd_bank.delete(),
+                    "ArrowLeft" if ctrl => cmd_bank.word_left(),
+                    "ArrowRight" if ctrl => cmd_bank.word_right(),
+                    "ArrowLeft" => cmd_bank.left(),
+                    "ArrowRight" => cmd_bank.right(),
+                    "Home" => cmd_bank.home(),
+                    "End" => cmd_bank.end(),
+                    _ => false,
+                });
             }
         }
-}
\ No newline at end of file
+}
+
+#[wasm_bindgen]
+pub fn wheelhandler(event: web_sys::WheelEvent, context: &web_sys::CanvasRenderingContext2d) {
+    let lines = match event.delta_mode() {
+        web_sys::WheelEvent::DOM_DELTA_LINE => event.delta_y(),
+        web_sys::WheelEvent::DOM_DELTA_PAGE => event.delta_y() * 10.0,
+        _ => event.delta_y() / get_line_height(),
+    };
+    // wheel down moves towards newer output
+    let lines = -lines.round() as isize;
+    if lines != 0 {
+        scroll(lines, context);
+    }
+}
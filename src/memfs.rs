@@ -0,0 +1,406 @@
+use crate::storage::Storage;
+use crate::vfs::{components, is_within, now, split_parent, FileSystem, Metadata, VfsError, VfsResult};
+use std::collections::BTreeMap;
+
+#[derive(Clone)]
+enum Node {
+    File { meta: Metadata, data: Vec<u8> },
+    Dir { meta: Metadata, entries: BTreeMap<String, Node> },
+}
+
+impl Node {
+    fn meta(&self) -> Metadata {
+        match self {
+            Node::File { meta, data } => Metadata { size: data.len(), ..meta.clone() },
+            Node::Dir { meta, .. } => Metadata { size: 4096, ..meta.clone() },
+        }
+    }
+
+    fn meta_mut(&mut self) -> &mut Metadata {
+        match self {
+            Node::File { meta, .. } | Node::Dir { meta, .. } => meta,
+        }
+    }
+}
+
+// A tree of directories and files kept in memory. On its own it is a tmpfs,
+// gone on reload; opened from a storage it is saved back there by `sync`.
+pub struct MemoryFs {
+    root: Node,
+    // owner given to everything created
+    owner: String,
+    // where `sync` saves the tree, and under which key
+    storage: Option<(Box<dyn Storage>, String)>,
+    // changed since the last sync
+    dirty: bool,
+}
+
+impl MemoryFs {
+    pub fn new(owner: &str) -> MemoryFs {
+        MemoryFs { root: Node::Dir { meta: MemoryFs::dir_meta(owner), entries: BTreeMap::new() }, owner: owner.to_string(), storage: None, dirty: false }
+    }
+
+    // The tree saved in `storage` under `key`, or an empty one if nothing is
+    // saved there. A save that can't be read is copied to `<key>.bak` before
+    // the empty tree can overwrite it, and the warning returned says so.
+    pub fn open(mut storage: Box<dyn Storage>, key: &str, owner: &str) -> (MemoryFs, Option<String>) {
+        let mut fs = MemoryFs::new(owner);
+        let mut warning = None;
+        if let Some(saved) = storage.get(key) {
+            match decode(&saved) {
+                Some(root) => fs.root = root,
+                None => {
+                    let backup = format!("{}.bak", key);
+                    storage.set(&backup, &saved);
+                    warning = Some(format!("The saved files couldn't be read, so they were kept as '{}' in browser storage and this session starts without them.", backup));
+                }
+            }
+        }
+        fs.storage = Some((storage, key.to_string()));
+        (fs, warning)
+    }
+
+    // Sets the mode and modification time of what is at `path`, for trees
+    // built from an archive.
+    pub fn set_meta(&mut self, path: &str, mode: u16, mtime: f64, owner: &str) -> VfsResult<()> {
+        let meta = self.node_mut(path)?.meta_mut();
+        meta.mode = mode;
+        meta.mtime = mtime;
+        meta.owner = owner.to_string();
+        self.dirty = true;
+        Ok(())
+    }
+
+    fn dir_meta(owner: &str) -> Metadata {
+        Metadata { is_dir: true, size: 0, mtime: now(), mode: 0o755, owner: owner.to_string() }
+    }
+
+    fn file_meta(&self) -> Metadata {
+        Metadata { is_dir: false, size: 0, mtime: now(), mode: 0o644, owner: self.owner.clone() }
+    }
+
+    fn node(&self, path: &str) -> VfsResult<&Node> {
+        let mut node = &self.root;
+        for part in components(path) {
+            node = match node {
+                Node::Dir { entries, .. } => entries.get(part).ok_or(VfsError::NotFound)?,
+                Node::File { .. } => return Err(VfsError::NotADirectory),
+            };
+        }
+        Ok(node)
+    }
+
+    fn node_mut(&mut self, path: &str) -> VfsResult<&mut Node> {
+        let mut node = &mut self.root;
+        for part in components(path) {
+            node = match node {
+                Node::Dir { entries, .. } => entries.get_mut(part).ok_or(VfsError::NotFound)?,
+                Node::File { .. } => return Err(VfsError::NotADirectory),
+            };
+        }
+        Ok(node)
+    }
+
+    // Runs `change` on the entries of the directory holding `path` and the
+    // last part of it. The directory only counts as modified if it succeeds.
+    fn change_parent<T>(&mut self, path: &str, change: impl FnOnce(&mut BTreeMap<String, Node>, &str) -> VfsResult<T>) -> VfsResult<T> {
+        let (parent, name) = split_parent(path).ok_or(VfsError::InvalidArgument)?;
+        let Node::Dir { entries, meta } = self.node_mut(parent)? else {
+            return Err(VfsError::NotADirectory);
+        };
+        let result = change(entries, name)?;
+        meta.mtime = now();
+        self.dirty = true;
+        Ok(result)
+    }
+
+    // puts `node` at `path`, which may only replace a file with a file
+    fn place(&mut self, path: &str, node: Node) -> VfsResult<()> {
+        self.change_parent(path, |entries, name| match (entries.get(name), &node) {
+            (Some(Node::Dir { .. }), _) => Err(VfsError::IsADirectory),
+            (Some(Node::File { .. }), Node::Dir { .. }) => Err(VfsError::NotADirectory),
+            _ => {
+                entries.insert(name.to_string(), node);
+                Ok(())
+            }
+        })
+    }
+}
+
+impl FileSystem for MemoryFs {
+    fn metadata(&self, path: &str) -> VfsResult<Metadata> {
+        Ok(self.node(path)?.meta())
+    }
+
+    fn read_dir(&self, path: &str) -> VfsResult<Vec<(String, Metadata)>> {
+        match self.node(path)? {
+            Node::Dir { entries, .. } => Ok(entries.iter().map(|(name, node)| (name.clone(), node.meta())).collect()),
+            Node::File { .. } => Err(VfsError::NotADirectory),
+        }
+    }
+
+    fn read(&self, path: &str) -> VfsResult<Vec<u8>> {
+        match self.node(path)? {
+            Node::File { data, .. } => Ok(data.clone()),
+            Node::Dir { .. } => Err(VfsError::IsADirectory),
+        }
+    }
+
+    fn write(&mut self, path: &str, contents: &[u8], append: bool) -> VfsResult<()> {
+        // the root has no parent to look in, but is still a directory
+        if self.is_dir(path) {
+            return Err(VfsError::IsADirectory);
+        }
+        let meta = self.file_meta();
+        self.change_parent(path, |entries, name| match entries.entry(name.to_string()).or_insert(Node::File { meta, data: Vec::new() }) {
+            Node::File { meta, data } => {
+                if !append {
+                    data.clear();
+                }
+                data.extend_from_slice(contents);
+                meta.mtime = now();
+                Ok(())
+            }
+            Node::Dir { .. } => Err(VfsError::IsADirectory),
+        })
+    }
+
+    fn touch(&mut self, path: &str) -> VfsResult<()> {
+        match self.node_mut(path) {
+            Ok(node) => {
+                node.meta_mut().mtime = now();
+                self.dirty = true;
+                Ok(())
+            }
+            Err(VfsError::NotFound) => self.write(path, &[], true),
+            Err(err) => Err(err),
+        }
+    }
+
+    fn create_dir(&mut self, path: &str) -> VfsResult<()> {
+        // the root, which is also what mount points resolve to
+        if split_parent(path).is_none() {
+            return Err(VfsError::AlreadyExists);
+        }
+        let meta = MemoryFs::dir_meta(&self.owner);
+        self.change_parent(path, |entries, name| {
+            if entries.contains_key(name) {
+                return Err(VfsError::AlreadyExists);
+            }
+            entries.insert(name.to_string(), Node::Dir { meta, entries: BTreeMap::new() });
+            Ok(())
+        })
+    }
+
+    fn remove_dir(&mut self, path: &str) -> VfsResult<()> {
+        match self.node(path)? {
+            Node::Dir { entries, .. } if !entries.is_empty() => return Err(VfsError::NotEmpty),
+            Node::Dir { .. } => {}
+            Node::File { .. } => return Err(VfsError::NotADirectory),
+        }
+        self.change_parent(path, |entries, name| {
+            entries.remove(name);
+            Ok(())
+        })
+    }
+
+    fn remove(&mut self, path: &str, recursive: bool) -> VfsResult<()> {
+        if self.is_dir(path) && !recursive {
+            return Err(VfsError::IsADirectory);
+        }
+        self.change_parent(path, |entries, name| entries.remove(name).map(|_| ()).ok_or(VfsError::NotFound))
+    }
+
+    fn copy(&mut self, from: &str, to: &str, recursive: bool) -> VfsResult<()> {
+        let mut node = self.node(from)?.clone();
+        if matches!(node, Node::Dir { .. }) {
+            if !recursive {
+                return Err(VfsError::IsADirectory);
+            }
+            if is_within(to, from) {
+                return Err(VfsError::InvalidArgument);
+            }
+        }
+        node.meta_mut().mtime = now();
+        self.place(to, node)
+    }
+
+    fn rename(&mut self, from: &str, to: &str) -> VfsResult<()> {
+        if is_within(to, from) {
+            return Err(VfsError::InvalidArgument);
+        }
+        let node = self.node(from)?.clone();
+        self.place(to, node)?;
+        self.change_parent(from, |entries, name| {
+            entries.remove(name);
+            Ok(())
+        })
+    }
+
+    // Saves the tree if anything changed. Called after every command rather
+    // than after every change, so a command touching many files saves once.
+    fn sync(&mut self) {
+        if let (Some((storage, key)), true) = (&mut self.storage, self.dirty) {
+            storage.set(key, &encode(&self.root));
+            self.dirty = false;
+        }
+    }
+}
+
+// The tree as text, one node per line with parents before their children:
+//   d <path> <mode> <owner> <mtime>
+//   f <path> <mode> <owner> <mtime> <t|x> <contents>
+// with tab separated fields. Contents are escaped text when they are UTF-8
+// (`t`) and hex otherwise (`x`).
+fn encode(root: &Node) -> String {
+    let mut lines = Vec::new();
+    encode_node(root, "", &mut lines);
+    lines.join("\n")
+}
+
+fn encode_node(node: &Node, path: &str, lines: &mut Vec<String>) {
+    let meta = node.meta();
+    let fields = format!("{}\t{:o}\t{}\t{}", escape(if path.is_empty() { "/" } else { path }), meta.mode, escape(&meta.owner), meta.mtime);
+    match node {
+        Node::Dir { entries, .. } => {
+            lines.push(format!("d\t{}", fields));
+            for (name, entry) in entries {
+                encode_node(entry, &format!("{}/{}", path, name), lines);
+            }
+        }
+        Node::File { data, .. } => {
+            let contents = match std::str::from_utf8(data) {
+                Ok(text) => format!("t\t{}", escape(text)),
+                Err(_) => format!("x\t{}", data.iter().map(|byte| format!("{:02x}", byte)).collect::<String>()),
+            };
+            lines.push(format!("f\t{}\t{}", fields, contents));
+        }
+    }
+}
+
+fn decode(saved: &str) -> Option<Node> {
+    let mut fs = MemoryFs::new("");
+    for line in saved.lines() {
+        let fields: Vec<&str> = line.split('\t').collect();
+        let (kind, path, mode, owner, mtime) = (*fields.first()?, unescape(fields.get(1)?), fields.get(2)?, fields.get(3)?, fields.get(4)?);
+        let meta = Metadata { is_dir: kind == "d", size: 0, mtime: mtime.parse().ok()?, mode: u16::from_str_radix(mode, 8).ok()?, owner: unescape(owner) };
+        let node = match (kind, fields.get(5).copied(), fields.get(6)) {
+            ("d", _, _) => Node::Dir { meta, entries: BTreeMap::new() },
+            ("f", Some("t"), Some(text)) => Node::File { meta, data: unescape(text).into_bytes() },
+            ("f", Some("x"), Some(hex)) => Node::File { meta, data: (0..hex.len()).step_by(2).map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok()).collect::<Option<_>>()? },
+            _ => return None,
+        };
+        match path.as_str() {
+            "/" => fs.root = node,
+            path => fs.place(path, node).ok()?,
+        }
+    }
+    Some(fs.root)
+}
+
+fn escape(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('\n', "\\n").replace('\t', "\\t")
+}
+
+fn unescape(text: &str) -> String {
+    let mut output = String::new();
+    let mut chars = text.chars();
+    while let Some(ch) = chars.next() {
+        match (ch, ch == '\\') {
+            (_, true) => match chars.next() {
+                Some('n') => output.push('\n'),
+                Some('t') => output.push('\t'),
+                Some(other) => output.push(other),
+                None => output.push('\\'),
+            },
+            _ => output.push(ch),
+        }
+    }
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::MemoryStorage;
+
+    #[test]
+    fn files_and_directories() {
+        let mut fs = MemoryFs::new("guest");
+        fs.create_dir_all("/guest/docs/old").unwrap();
+        fs.write("/guest/docs/a.txt", b"hello", false).unwrap();
+        fs.write("/guest/docs/a.txt", b" world", true).unwrap();
+        assert_eq!(fs.read("/guest/docs/a.txt").unwrap(), b"hello world");
+        assert_eq!(fs.metadata("/guest/docs/a.txt").unwrap().size, 11);
+        assert_eq!(fs.metadata("/guest/docs").unwrap().mode_string(), "drwxr-xr-x");
+        let names: Vec<String> = fs.read_dir("/guest/docs").unwrap().into_iter().map(|(name, _)| name).collect();
+        assert_eq!(names, vec!["a.txt", "old"]);
+
+        assert_eq!(fs.read("/guest/docs"), Err(VfsError::IsADirectory));
+        assert_eq!(fs.write("/nope/a", b"", false), Err(VfsError::NotFound));
+        assert_eq!(fs.write("/guest/docs/a.txt/b", b"", false), Err(VfsError::NotADirectory));
+        assert_eq!(fs.remove_dir("/guest/docs"), Err(VfsError::NotEmpty));
+        assert_eq!(fs.remove("/guest/docs", false), Err(VfsError::IsADirectory));
+        assert_eq!(fs.create_dir("/guest/docs"), Err(VfsError::AlreadyExists));
+    }
+
+    #[test]
+    fn failed_writes_change_nothing() {
+        let mut fs = MemoryFs::new("guest");
+        fs.create_dir("/docs").unwrap();
+        let mtime = fs.metadata("/").unwrap().mtime;
+        fs.dirty = false;
+        assert_eq!(fs.write("/", b"x", false), Err(VfsError::IsADirectory));
+        assert_eq!(fs.write("/docs", b"x", true), Err(VfsError::IsADirectory));
+        assert_eq!(fs.remove("/gone", false), Err(VfsError::NotFound));
+        assert!(!fs.dirty);
+        assert_eq!(fs.metadata("/").unwrap().mtime, mtime);
+    }
+
+    #[test]
+    fn copy_and_rename() {
+        let mut fs = MemoryFs::new("guest");
+        fs.create_dir_all("/a/b").unwrap();
+        fs.write("/a/b/f", b"x", false).unwrap();
+        assert_eq!(fs.copy("/a", "/c", false), Err(VfsError::IsADirectory));
+        fs.copy("/a", "/c", true).unwrap();
+        assert_eq!(fs.read("/c/b/f").unwrap(), b"x");
+        assert_eq!(fs.rename("/a", "/a/b/a"), Err(VfsError::InvalidArgument));
+        fs.rename("/a/b/f", "/g").unwrap();
+        assert!(fs.metadata("/a/b/f").is_err());
+        assert_eq!(fs.read("/g").unwrap(), b"x");
+        fs.remove("/c", true).unwrap();
+        assert!(!fs.is_dir("/c"));
+    }
+
+    #[test]
+    fn the_tree_survives_a_reload() {
+        let (mut fs, _) = MemoryFs::open(Box::new(MemoryStorage::default()), "home", "guest");
+        fs.write("/notes.txt", "tab\there\nback\\slash ü".as_bytes(), false).unwrap();
+        fs.write("/blob", &[0, 159, 255], false).unwrap();
+        fs.sync();
+        assert!(!fs.dirty);
+
+        let mut storage = MemoryStorage::default();
+        storage.set("home", &fs.storage.as_ref().and_then(|(storage, key)| storage.get(key)).unwrap());
+        let (reloaded, warning) = MemoryFs::open(Box::new(storage), "home", "guest");
+        assert_eq!(warning, None);
+        assert_eq!(reloaded.read("/notes.txt").unwrap(), "tab\there\nback\\slash ü".as_bytes());
+        assert_eq!(reloaded.read("/blob").unwrap(), vec![0, 159, 255]);
+        assert_eq!(reloaded.metadata("/notes.txt").unwrap(), fs.metadata("/notes.txt").unwrap());
+    }
+
+    #[test]
+    fn unreadable_saves_are_backed_up() {
+        let mut storage = MemoryStorage::default();
+        storage.set("home", "d\t/\tzz");
+        let (mut fs, warning) = MemoryFs::open(Box::new(storage), "home", "guest");
+        assert!(warning.is_some_and(|warning| warning.contains("'home.bak'")));
+        assert_eq!(fs.read_dir("/"), Ok(Vec::new()));
+        fs.write("/new", b"", false).unwrap();
+        fs.sync();
+        let (storage, _) = fs.storage.as_ref().unwrap();
+        assert_eq!(storage.get("home.bak").as_deref(), Some("d\t/\tzz"));
+        assert!(storage.get("home").is_some_and(|saved| saved.contains("/new")));
+    }
+}
@@ -0,0 +1,487 @@
+use crate::env::is_valid_name;
+use crate::lexer::{tokenize, Direction, ParseError, Token, Word, WordPart};
+use std::sync::Arc;
+
+// How a command in a list depends on the one before it.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Connector {
+    // `;`, a newline or the start of the line: always runs
+    Then,
+    // `&&`: runs if the previous command succeeded
+    And,
+    // `||`: runs if the previous command failed
+    Or,
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct Redirect {
+    // the stream: 0, 1 or 2, which is 0 for a bare `<` and 1 for a bare `>`
+    pub fd: u8,
+    pub direction: Direction,
+    pub target: Word,
+}
+
+// `NAME=value` before a command
+#[derive(Clone, PartialEq, Debug)]
+pub struct Assignment {
+    pub name: String,
+    pub value: Word,
+}
+
+#[derive(Clone, PartialEq, Debug, Default)]
+pub struct SimpleCommand {
+    pub assignments: Vec<Assignment>,
+    pub words: Vec<Word>,
+    pub redirects: Vec<Redirect>,
+}
+
+// `if`, loops, `case` and `{ ...; }`: commands made of other commands.
+#[derive(Clone, PartialEq, Debug)]
+pub enum Compound {
+    Group(List),
+    // the condition and body of the `if` and each `elif`, then the `else`
+    If { branches: Vec<(List, List)>, otherwise: Option<List> },
+    // `while`, or `until` when the loop runs while the condition fails
+    While { condition: List, body: List, until: bool },
+    // `for name in words`, or over the positional parameters without `in`
+    For { name: String, words: Option<Vec<Word>>, body: List },
+    // the word to match, then each arm's patterns and body
+    Case { word: Word, arms: Vec<(Vec<Word>, List)> },
+}
+
+// One command of a pipeline.
+#[derive(Clone, PartialEq, Debug)]
+pub enum Stage {
+    Simple(SimpleCommand),
+    // with the redirects after its closing keyword
+    Compound(Compound, Vec<Redirect>),
+    // `name() { ...; }`, which defines the function when it runs
+    Function(String, Arc<Stage>),
+}
+
+// Commands joined by `|`, each feeding its output to the next.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Pipeline {
+    pub commands: Vec<Stage>,
+}
+
+// Pipelines in the order they run, each with what decides whether it does.
+pub type List = Vec<(Connector, Pipeline)>;
+
+// words that end a list when they come where a command would
+const TERMINATORS: [&str; 8] = ["then", "elif", "else", "fi", "do", "done", "esac", "}"];
+
+// Parses a command line, or several separated by newlines, like a script.
+pub fn parse(source: &str) -> Result<List, ParseError> {
+    let mut parser = Parser { tokens: tokenize(source)?, next: 0, end: source.len() };
+    let list = parser.list()?;
+    match parser.peek() {
+        Some(_) => Err(parser.unexpected()),
+        None => Ok(list),
+    }
+}
+
+struct Parser {
+    tokens: Vec<(Token, usize)>,
+    next: usize,
+    // the length of the source, where errors about running out of input go
+    end: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.next).map(|(token, _)| token)
+    }
+
+    fn advance(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.next).map(|(token, _)| token.clone());
+        self.next += 1;
+        token
+    }
+
+    fn eat(&mut self, operator: &str) -> bool {
+        let found = matches!(self.peek(), Some(Token::Operator(found)) if *found == operator);
+        if found {
+            self.next += 1;
+        }
+        found
+    }
+
+    fn skip_newlines(&mut self) {
+        while self.eat("\n") {}
+    }
+
+    // whether the next token is the unquoted word `keyword`
+    fn at(&self, keyword: &str) -> bool {
+        matches!(self.peek(), Some(Token::Word(word)) if word.literal() == Some(keyword))
+    }
+
+    fn eat_keyword(&mut self, keyword: &str) -> bool {
+        let found = self.at(keyword);
+        if found {
+            self.next += 1;
+        }
+        found
+    }
+
+    fn keyword(&mut self, keyword: &str) -> Result<(), ParseError> {
+        match self.eat_keyword(keyword) {
+            true => Ok(()),
+            false => Err(self.unexpected()),
+        }
+    }
+
+    fn word(&mut self) -> Result<Word, ParseError> {
+        match self.peek() {
+            Some(Token::Word(_)) => match self.advance() {
+                Some(Token::Word(word)) => Ok(word),
+                _ => unreachable!(),
+            },
+            _ => Err(self.unexpected()),
+        }
+    }
+
+    // the error for the token about to be read
+    fn unexpected(&self) -> ParseError {
+        match self.tokens.get(self.next) {
+            Some((token, pos)) => ParseError::new(format!("syntax error near unexpected token `{}'", token.describe()), *pos),
+            None => ParseError::new("syntax error: unexpected end of file", self.end),
+        }
+    }
+
+    // pipelines separated by `;`, newlines, `&&` and `||`
+    fn list(&mut self) -> Result<List, ParseError> {
+        let mut list = Vec::new();
+        self.skip_newlines();
+        while matches!(self.peek(), Some(Token::Word(_) | Token::Redirect { .. })) && !TERMINATORS.iter().any(|keyword| self.at(keyword)) {
+            list.push((Connector::Then, self.pipeline()?));
+            loop {
+                let connector = if self.eat("&&") {
+                    Connector::And
+                } else if self.eat("||") {
+                    Connector::Or
+                } else {
+                    break;
+                };
+                self.skip_newlines();
+                list.push((connector, self.pipeline()?));
+            }
+            if !self.eat(";") && !self.eat("\n") {
+                break;
+            }
+            self.skip_newlines();
+        }
+        Ok(list)
+    }
+
+    // a list that has to have something in it, like the body of a loop
+    fn body(&mut self) -> Result<List, ParseError> {
+        let list = self.list()?;
+        match list.is_empty() {
+            true => Err(self.unexpected()),
+            false => Ok(list),
+        }
+    }
+
+    fn pipeline(&mut self) -> Result<Pipeline, ParseError> {
+        let mut commands = vec![self.stage()?];
+        while self.eat("|") {
+            self.skip_newlines();
+            commands.push(self.stage()?);
+        }
+        Ok(Pipeline { commands })
+    }
+
+    fn stage(&mut self) -> Result<Stage, ParseError> {
+        if TERMINATORS.iter().any(|keyword| self.at(keyword)) {
+            return Err(self.unexpected());
+        }
+        let defines_function = self.at("function") || matches!(
+            (self.tokens.get(self.next + 1), self.tokens.get(self.next + 2)),
+            (Some((Token::Operator("("), _)), Some((Token::Operator(")"), _)))
+        );
+        if defines_function {
+            return self.function();
+        }
+        match self.compound()? {
+            Some(compound) => Ok(Stage::Compound(compound, self.redirects()?)),
+            None => Ok(Stage::Simple(self.simple_command()?)),
+        }
+    }
+
+    // `name() body` or `function name body`, where the body is a compound
+    // command
+    fn function(&mut self) -> Result<Stage, ParseError> {
+        let keyword = self.eat_keyword("function");
+        let name = match self.peek() {
+            Some(Token::Word(word)) => word.literal().filter(|name| !name.contains('=') && !TERMINATORS.contains(name)).map(str::to_string),
+            _ => None,
+        };
+        let Some(name) = name else { return Err(self.unexpected()) };
+        self.next += 1;
+        if self.eat("(") {
+            if !self.eat(")") {
+                return Err(self.unexpected());
+            }
+        } else if !keyword {
+            return Err(self.unexpected());
+        }
+        self.skip_newlines();
+        match self.compound()? {
+            Some(compound) => Ok(Stage::Function(name, Arc::new(Stage::Compound(compound, self.redirects()?)))),
+            None => Err(self.unexpected()),
+        }
+    }
+
+    // the compound command starting here, if one does
+    fn compound(&mut self) -> Result<Option<Compound>, ParseError> {
+        let compound = if self.eat_keyword("if") {
+            self.if_clause()?
+        } else if self.eat_keyword("while") {
+            self.while_clause(false)?
+        } else if self.eat_keyword("until") {
+            self.while_clause(true)?
+        } else if self.eat_keyword("for") {
+            self.for_clause()?
+        } else if self.eat_keyword("case") {
+            self.case_clause()?
+        } else if self.eat_keyword("{") {
+            let list = self.body()?;
+            self.keyword("}")?;
+            Compound::Group(list)
+        } else {
+            return Ok(None);
+        };
+        Ok(Some(compound))
+    }
+
+    fn if_clause(&mut self) -> Result<Compound, ParseError> {
+        let mut branches = Vec::new();
+        loop {
+            let condition = self.body()?;
+            self.keyword("then")?;
+            branches.push((condition, self.body()?));
+            if !self.eat_keyword("elif") {
+                break;
+            }
+        }
+        let otherwise = match self.eat_keyword("else") {
+            true => Some(self.body()?),
+            false => None,
+        };
+        self.keyword("fi")?;
+        Ok(Compound::If { branches, otherwise })
+    }
+
+    fn while_clause(&mut self, until: bool) -> Result<Compound, ParseError> {
+        let condition = self.body()?;
+        self.keyword("do")?;
+        let body = self.body()?;
+        self.keyword("done")?;
+        Ok(Compound::While { condition, body, until })
+    }
+
+    fn for_clause(&mut self) -> Result<Compound, ParseError> {
+        let pos = self.tokens.get(self.next).map(|(_, pos)| *pos).unwrap_or(self.end);
+        let word = self.word()?;
+        let name = match word.literal() {
+            Some(name) if is_valid_name(name) => name.to_string(),
+            _ => return Err(ParseError::new(format!("`{}': not a valid identifier", word.literal().unwrap_or("word")), pos)),
+        };
+        self.skip_newlines();
+        let words = match self.eat_keyword("in") {
+            true => {
+                let mut words = Vec::new();
+                while let Some(Token::Word(_)) = self.peek() {
+                    words.push(self.word()?);
+                }
+                if !self.eat(";") && !self.eat("\n") {
+                    return Err(self.unexpected());
+                }
+                Some(words)
+            }
+            false => {
+                self.eat(";");
+                None
+            }
+        };
+        self.skip_newlines();
+        self.keyword("do")?;
+        let body = self.body()?;
+        self.keyword("done")?;
+        Ok(Compound::For { name, words, body })
+    }
+
+    fn case_clause(&mut self) -> Result<Compound, ParseError> {
+        let word = self.word()?;
+        self.skip_newlines();
+        self.keyword("in")?;
+        let mut arms = Vec::new();
+        loop {
+            self.skip_newlines();
+            if self.eat_keyword("esac") {
+                break;
+            }
+            self.eat("(");
+            let mut patterns = vec![self.word()?];
+            while self.eat("|") {
+                patterns.push(self.word()?);
+            }
+            if !self.eat(")") {
+                return Err(self.unexpected());
+            }
+            arms.push((patterns, self.list()?));
+            // only the last arm can leave out the `;;`
+            if !self.eat(";;") {
+                self.skip_newlines();
+                self.keyword("esac")?;
+                break;
+            }
+        }
+        Ok(Compound::Case { word, arms })
+    }
+
+    fn redirects(&mut self) -> Result<Vec<Redirect>, ParseError> {
+        let mut redirects = Vec::new();
+        while let Some(&Token::Redirect { fd, direction }) = self.peek() {
+            self.next += 1;
+            redirects.push(Redirect { fd, direction, target: self.word()? });
+        }
+        Ok(redirects)
+    }
+
+    fn simple_command(&mut self) -> Result<SimpleCommand, ParseError> {
+        let mut command = SimpleCommand::default();
+        loop {
+            match self.peek() {
+                Some(Token::Word(_)) => {
+                    let Some(Token::Word(word)) = self.advance() else { unreachable!() };
+                    match assignment(&word) {
+                        Some(assignment) if command.words.is_empty() => command.assignments.push(assignment),
+                        _ => command.words.push(word),
+                    }
+                }
+                Some(Token::Redirect { .. }) => command.redirects.extend(self.redirects()?),
+                _ => break,
+            }
+        }
+        if command == SimpleCommand::default() {
+            return Err(self.unexpected());
+        }
+        Ok(command)
+    }
+}
+
+// `word` as an assignment, if it starts with an unquoted `NAME=`
+fn assignment(word: &Word) -> Option<Assignment> {
+    let Some(WordPart::Literal(first)) = word.parts.first() else { return None };
+    let (name, value) = first.split_once('=').filter(|(name, _)| is_valid_name(name))?;
+    let mut parts = word.parts[1..].to_vec();
+    if !value.is_empty() {
+        parts.insert(0, WordPart::Literal(value.to_string()));
+    }
+    Some(Assignment { name: name.to_string(), value: Word { parts, pos: word.pos + name.len() + 1 } })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn simple(stage: &Stage) -> &SimpleCommand {
+        match stage {
+            Stage::Simple(command) => command,
+            _ => panic!("not a simple command"),
+        }
+    }
+
+    // the first word of each command, pipeline by pipeline
+    fn names(list: &List) -> Vec<(Connector, Vec<String>)> {
+        list.iter().map(|(connector, pipeline)| {
+            (*connector, pipeline.commands.iter().map(|stage| simple(stage).words[0].literal().unwrap_or_default().to_string()).collect())
+        }).collect()
+    }
+
+    #[test]
+    fn lists_and_pipelines() {
+        let list = parse("calc add 1 x && echo ok || echo 'fail;ed'; help | grep calc;\n\nls # done").unwrap();
+        assert_eq!(names(&list), vec![
+            (Connector::Then, vec!["calc".to_string()]),
+            (Connector::And, vec!["echo".to_string()]),
+            (Connector::Or, vec!["echo".to_string()]),
+            (Connector::Then, vec!["help".to_string(), "grep".to_string()]),
+            (Connector::Then, vec!["ls".to_string()]),
+        ]);
+        assert_eq!(simple(&list[2].1.commands[0]).words[1].parts, vec![WordPart::Quoted("fail;ed".to_string())]);
+        assert_eq!(parse(r#"echo "a|b" 'c|d' e\|f"#).unwrap()[0].1.commands.len(), 1);
+        assert_eq!(parse("").unwrap(), Vec::new());
+    }
+
+    #[test]
+    fn assignments_and_redirects() {
+        let list = parse("A=1 B= C=\"x y\" calc add 1 2 > /tmp/sum 2>>err D=4").unwrap();
+        let command = simple(&list[0].1.commands[0]);
+        let names: Vec<&str> = command.assignments.iter().map(|assignment| assignment.name.as_str()).collect();
+        assert_eq!(names, vec!["A", "B", "C"]);
+        assert_eq!(command.assignments[1].value.parts, Vec::new());
+        assert_eq!(command.assignments[2].value.parts, vec![WordPart::Quoted("x y".to_string())]);
+        assert_eq!(command.words.len(), 5);
+        assert_eq!(command.words[4].literal(), Some("D=4"));
+        assert_eq!(command.redirects.iter().map(|redirect| (redirect.fd, redirect.direction, redirect.target.literal())).collect::<Vec<_>>(), vec![
+            (1, Direction::Write, Some("/tmp/sum")),
+            (2, Direction::Append, Some("err")),
+        ]);
+        assert_eq!(simple(&parse("X=1").unwrap()[0].1.commands[0]).words, Vec::new());
+    }
+
+    #[test]
+    fn syntax_errors_have_positions() {
+        assert_eq!(parse("; help").unwrap_err(), ParseError::new("syntax error near unexpected token `;'", 0));
+        assert_eq!(parse("help && || echo").unwrap_err(), ParseError::new("syntax error near unexpected token `||'", 8));
+        assert_eq!(parse("| grep x").unwrap_err().pos, 0);
+        assert_eq!(parse("help |").unwrap_err(), ParseError::new("syntax error: unexpected end of file", 6));
+        assert_eq!(parse("echo >").unwrap_err(), ParseError::new("syntax error: unexpected end of file", 6));
+        assert_eq!(parse("echo > > x").unwrap_err().pos, 7);
+        assert_eq!(parse("echo ok ;; ls").unwrap_err().message, "syntax error near unexpected token `;;'");
+        assert_eq!(parse("sleep 1 & ls").unwrap_err().message, "syntax error near unexpected token `&'");
+        assert_eq!(parse("echo hi; fi").unwrap_err(), ParseError::new("syntax error near unexpected token `fi'", 9));
+        assert_eq!(parse("if true; then fi").unwrap_err().pos, 14);
+        assert_eq!(parse("while true; do echo").unwrap_err().message, "syntax error: unexpected end of file");
+        assert_eq!(parse("for 1x in a; do ls; done").unwrap_err(), ParseError::new("`1x': not a valid identifier", 4));
+        assert_eq!(parse("case x in a) ls b) ls;; esac").unwrap_err().message, "syntax error near unexpected token `)'");
+    }
+
+    #[test]
+    fn compound_commands() {
+        let script = "
+            if test a; then echo 1; elif test b
+            then echo 2; else echo 3; fi > out
+            for name in a 'b c'; do echo $name; done
+            for arg; do echo $arg; done
+            until false; do break; done | grep x
+            case $1 in
+                a|b) echo ab;;
+                (*) echo other
+            esac
+            greet() {
+                echo hi
+            }
+            function bye { echo fi; }
+        ";
+        let list = parse(script).unwrap();
+        let stages: Vec<&Stage> = list.iter().map(|(_, pipeline)| &pipeline.commands[0]).collect();
+        let Stage::Compound(Compound::If { branches, otherwise }, redirects) = stages[0] else { panic!("not an if") };
+        assert_eq!((branches.len(), otherwise.as_ref().map(Vec::len), redirects.len()), (2, Some(1), 1));
+        let Stage::Compound(Compound::For { name, words: Some(words), body }, _) = stages[1] else { panic!("not a for") };
+        assert_eq!((name.as_str(), words.len(), body.len()), ("name", 2, 1));
+        assert!(matches!(stages[2], Stage::Compound(Compound::For { words: None, .. }, _)));
+        assert!(matches!(stages[3], Stage::Compound(Compound::While { until: true, .. }, _)));
+        assert_eq!(list[3].1.commands.len(), 2);
+        let Stage::Compound(Compound::Case { arms, .. }, _) = stages[4] else { panic!("not a case") };
+        assert_eq!(arms.iter().map(|(patterns, body)| (patterns.len(), body.len())).collect::<Vec<_>>(), vec![(2, 1), (1, 1)]);
+        let Stage::Function(name, body) = stages[5] else { panic!("not a function") };
+        assert_eq!(name, "greet");
+        assert!(matches!(**body, Stage::Compound(Compound::Group(_), _)));
+        assert!(matches!(stages[6], Stage::Function(name, _) if name == "bye"));
+        assert_eq!(names(&parse("echo if then; echo done").unwrap()).len(), 2);
+    }
+}
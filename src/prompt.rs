@@ -0,0 +1,107 @@
+use crate::cmd::LAST_STATUS;
+use crate::env::{Env, ENV};
+use crate::history::HISTORY;
+use crate::theme::THEME;
+
+// The default PS1: "[status] " after a failure, then user@host: cwd $
+pub fn default_ps1() -> String {
+    format!("\\F{}\\u@\\h: {}{}\\w{} {}\\$ {}", THEME.user, THEME.text, THEME.cwd, THEME.text, THEME.prompt, THEME.text)
+}
+
+// What the prompt escapes are filled in from, besides the environment.
+pub struct PromptState {
+    pub status: i32,
+    // the number the next history entry will get
+    pub history: usize,
+    // local time as (hours, minutes, seconds)
+    pub time: (u32, u32, u32),
+}
+
+// The prompt for the current session, from $PS1.
+pub fn prompt() -> String {
+    let now = js_sys::Date::new_0();
+    let state = PromptState {
+        status: *LAST_STATUS.lock().unwrap(),
+        history: HISTORY.lock().unwrap().entries().len() + 1,
+        time: (now.get_hours(), now.get_minutes(), now.get_seconds()),
+    };
+    let env = ENV.lock().unwrap();
+    render(env.get("PS1").unwrap_or("\\$ "), &env, &state)
+}
+
+// Expands the escapes in a PS1 template:
+//   \u user, \h host up to the first dot, \H whole host, \w cwd with ~ for
+//   home, \W last part of the cwd, \t HH:MM:SS, \T 12-hour HH:MM:SS, \A HH:MM,
+//   \@ 12-hour HH:MM am/pm, \? last exit status, \F "[status] " in the error
+//   colour after a failure, \! history number, \$ `#` for root and `$`
+//   otherwise, \n newline, \e escape (for ANSI colours), \\ backslash, and
+//   \[ \] which are accepted and ignored.
+// Anything else, including colour markup like \#RRGGBB, is left as it is.
+pub fn render(template: &str, env: &Env, state: &PromptState) -> String {
+    let (hours, minutes, seconds) = state.time;
+    let twelve = if hours % 12 == 0 { 12 } else { hours % 12 };
+    let host = env.get("HOST").unwrap_or_default();
+    let cwd = env.short_pwd();
+
+    let mut output = String::new();
+    let mut chars = template.chars();
+    while let Some(ch) = chars.next() {
+        if ch != '\\' {
+            output.push(ch);
+            continue;
+        }
+        match chars.next() {
+            Some('u') => output += env.get("USER").unwrap_or_default(),
+            Some('h') => output += host.split('.').next().unwrap_or_default(),
+            Some('H') => output += host,
+            Some('w') => output += &cwd,
+            Some('W') => output += cwd.rsplit('/').find(|part| !part.is_empty()).unwrap_or("/"),
+            Some('t') => output += &format!("{:02}:{:02}:{:02}", hours, minutes, seconds),
+            Some('T') => output += &format!("{:02}:{:02}:{:02}", twelve, minutes, seconds),
+            Some('A') => output += &format!("{:02}:{:02}", hours, minutes),
+            Some('@') => output += &format!("{:02}:{:02} {}", twelve, minutes, if hours < 12 { "AM" } else { "PM" }),
+            Some('?') => output += &state.status.to_string(),
+            Some('F') if state.status != 0 => output += &format!("{}[{}] {}", THEME.error, state.status, THEME.text),
+            Some('F') => {}
+            Some('!') => output += &state.history.to_string(),
+            Some('$') => output.push(if env.get("USER") == Some("root") { '#' } else { '$' }),
+            Some('n') => output.push('\n'),
+            Some('e') => output.push('\x1b'),
+            Some('\\') => output.push('\\'),
+            Some('[' | ']') => {}
+            Some(other) => {
+                output.push('\\');
+                output.push(other);
+            }
+            None => output.push('\\'),
+        }
+    }
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn state(status: i32) -> PromptState {
+        PromptState { status, history: 7, time: (13, 5, 9) }
+    }
+
+    #[test]
+    fn escapes() {
+        let mut env = Env::new();
+        env.set("HOST", "buudunn.local");
+        env.set("PWD", "/home/guest/docs");
+        assert_eq!(render("\\u@\\h (\\H) \\w \\W \\$ ", &env, &state(0)), "guest@buudunn (buudunn.local) ~/docs docs $ ");
+        assert_eq!(render("\\t \\A \\@ !\\! ?\\?", &env, &state(2)), "13:05:09 13:05 01:05 PM !7 ?2");
+        assert_eq!(render("\\[\\e[31m\\]x\\\\\\q", &env, &state(0)), "\x1b[31mx\\\\q");
+    }
+
+    #[test]
+    fn markup_passes_through_and_failures_are_marked() {
+        let env = Env::new();
+        assert_eq!(render("\\#FF0000\\u\\*b", &env, &state(0)), "\\#FF0000guest\\*b");
+        assert_eq!(render("\\F$ ", &env, &state(0)), "$ ");
+        assert_eq!(render("\\F$ ", &env, &state(127)), format!("{}[127] {}$ ", THEME.error, THEME.text));
+    }
+}
@@ -0,0 +1,76 @@
+use crate::screen::{Color, Screen, Style};
+use wasm_bindgen::JsValue;
+use web_sys::CanvasRenderingContext2d;
+
+// pixel position of the baseline of the first cell
+pub const ORIGIN: (f64, f64) = (10.0, 20.0);
+
+pub fn cell_width(font_size: f64) -> f64 {
+    font_size / 2.0
+}
+
+pub fn line_height(font_size: f64) -> f64 {
+    font_size + 4.0
+}
+
+// how many columns and rows of cells fit on a canvas of the given size
+pub fn grid_size(width: f64, height: f64, font_size: f64) -> (usize, usize) {
+    let cols = ((width - ORIGIN.0 * 3.0) / cell_width(font_size)).floor();
+    let rows = ((height - ORIGIN.1 + font_size) / line_height(font_size)).floor();
+    (cols.max(1.0) as usize, rows.max(1.0) as usize)
+}
+
+fn font(style: &Style, font_size: f64) -> String {
+    let italic = if style.italic { "italic " } else { "" };
+    let bold = if style.bold { "bold " } else { "" };
+    format!("{}{}{}px Gohu", italic, bold, font_size)
+}
+
+// Redraws every viewport row the screen has marked as changed since the last
+// render.
+pub fn render(screen: &mut Screen, context: &CanvasRenderingContext2d, font_size: f64) {
+    let width = context.canvas().map(|canvas| canvas.width() as f64).unwrap_or(0.0);
+    let cell_w = cell_width(font_size);
+    let line_h = line_height(font_size);
+
+    let top = screen.viewport_top();
+    let cursor = screen.cursor();
+    for row in screen.take_dirty() {
+        let line = top + row;
+        let y = ORIGIN.1 + row as f64 * line_h;
+        context.set_fill_style(&JsValue::from_str(&Color::BLACK.to_css()));
+        context.fill_rect(0.0, y - font_size, width, line_h);
+
+        let Some(row) = screen.line(line) else { continue };
+        let mut current_font = None;
+        for (col, cell) in row.cells.iter().enumerate() {
+            let x = ORIGIN.0 + col as f64 * cell_w;
+            let (fg, bg) = cell.style.colors();
+            if bg != Color::BLACK {
+                context.set_fill_style(&JsValue::from_str(&bg.to_css()));
+                context.fill_rect(x, y - font_size, cell_w, line_h);
+            }
+            context.set_fill_style(&JsValue::from_str(&fg.to_css()));
+            if cell.ch != ' ' {
+                let cell_font = font(&cell.style, font_size);
+                if current_font.as_ref() != Some(&cell_font) {
+                    context.set_font(&cell_font);
+                    current_font = Some(cell_font);
+                }
+                context.fill_text(&cell.ch.to_string(), x, y).unwrap();
+            }
+            if cell.style.underline {
+                context.fill_rect(x, y + 2.0, cell_w, 1.0);
+            }
+            if cell.style.strikethrough {
+                context.fill_rect(x, y - font_size * 0.35, cell_w, 1.0);
+            }
+        }
+        if screen.cursor_visible() && cursor.0 == line {
+            let x = ORIGIN.0 + cursor.1 as f64 * cell_w;
+            context.set_fill_style(&JsValue::from_str(&Color::WHITE.to_css()));
+            context.fill_rect(x, y + 1.0, cell_w, 2.0);
+        }
+    }
+    context.set_fill_style(&JsValue::from_str(&Color::WHITE.to_css()));
+}
@@ -0,0 +1,191 @@
+use crate::memfs::MemoryFs;
+use crate::vfs::{normalize, split_parent, FileSystem, Metadata, VfsError, VfsResult};
+
+// A tree that can be looked at but not changed, read in full when it is
+// mounted: a tar archive, or a directory served over HTTP.
+pub struct ReadOnlyFs {
+    tree: MemoryFs,
+}
+
+impl ReadOnlyFs {
+    // The contents of a tar archive. Links and other special entries are
+    // left out.
+    pub fn from_tar(archive: &[u8], owner: &str) -> Result<ReadOnlyFs, String> {
+        let mut tree = MemoryFs::new(owner);
+        let mut offset = 0;
+        while offset < archive.len() {
+            let header = archive.get(offset..offset + 512).ok_or("truncated archive")?;
+            if header.iter().all(|&byte| byte == 0) {
+                break;
+            }
+            let checksum: u64 = header.iter().enumerate().map(|(i, &byte)| if (148..156).contains(&i) { b' ' as u64 } else { byte as u64 }).sum();
+            if octal(&header[148..156]) != Some(checksum) {
+                return Err("not a tar archive".to_string());
+            }
+            let mut name = text(&header[0..100]);
+            if &header[257..262] == b"ustar" && header[345] != 0 {
+                name = format!("{}/{}", text(&header[345..500]), name);
+            }
+            let path = normalize("/", "/", &name);
+            // the field holds more than usize does on wasm32
+            let size = usize::try_from(octal(&header[124..136]).unwrap_or_default()).map_err(|_| "truncated archive")?;
+            let mode = octal(&header[100..108]).unwrap_or(0o644) as u16 & 0o777;
+            let mtime = octal(&header[136..148]).unwrap_or_default() as f64 * 1000.0;
+            let uname = text(&header[265..297]);
+
+            let start = offset + 512;
+            let end = start.checked_add(size).ok_or("truncated archive")?;
+            let data = archive.get(start..end).ok_or("truncated archive")?;
+            offset = size.div_ceil(512).checked_mul(512).and_then(|padded| start.checked_add(padded)).ok_or("truncated archive")?;
+
+            let result = match header[156] {
+                b'5' => tree.create_dir_all(&path),
+                b'0' | 0 => match split_parent(&path) {
+                    Some((parent, _)) => tree.create_dir_all(parent).and_then(|_| tree.write(&path, data, false)),
+                    None => continue,
+                },
+                _ => continue,
+            };
+            result.and_then(|_| tree.set_meta(&path, mode, mtime, if uname.is_empty() { owner } else { &uname }))
+                .map_err(|err| format!("{}: {}", name, err))?;
+        }
+        Ok(ReadOnlyFs { tree })
+    }
+
+    // The directory at `url`, which lists its contents in `index.txt`: one
+    // path per line, relative to `url`, with a `/` after directories.
+    pub async fn fetch(url: &str, owner: &str) -> Result<ReadOnlyFs, String> {
+        let url = url.trim_end_matches('/');
+        let index = get(&format!("{}/index.txt", url)).await?;
+        let mut tree = MemoryFs::new(owner);
+        for line in String::from_utf8_lossy(&index).lines().map(str::trim).filter(|line| !line.is_empty()) {
+            let path = normalize("/", "/", line);
+            let result = match line.ends_with('/') {
+                true => tree.create_dir_all(&path),
+                false => {
+                    let data = get(&format!("{}{}", url, path)).await?;
+                    let parent = split_parent(&path).map(|(parent, _)| parent).unwrap_or("/");
+                    tree.create_dir_all(parent).and_then(|_| tree.write(&path, &data, false))
+                }
+            };
+            result.map_err(|err| format!("{}: {}", line, err))?;
+        }
+        Ok(ReadOnlyFs { tree })
+    }
+}
+
+async fn get(url: &str) -> Result<Vec<u8>, String> {
+    let response = reqwest::get(url).await.and_then(|response| response.error_for_status()).map_err(|err| format!("{}: {}", url, err))?;
+    response.bytes().await.map(|bytes| bytes.to_vec()).map_err(|err| format!("{}: {}", url, err))
+}
+
+// a NUL-terminated header field
+fn text(field: &[u8]) -> String {
+    let end = field.iter().position(|&byte| byte == 0).unwrap_or(field.len());
+    String::from_utf8_lossy(&field[..end]).to_string()
+}
+
+// a NUL or space padded octal header field
+fn octal(field: &[u8]) -> Option<u64> {
+    u64::from_str_radix(text(field).trim(), 8).ok()
+}
+
+impl FileSystem for ReadOnlyFs {
+    fn metadata(&self, path: &str) -> VfsResult<Metadata> {
+        self.tree.metadata(path)
+    }
+
+    fn read_dir(&self, path: &str) -> VfsResult<Vec<(String, Metadata)>> {
+        self.tree.read_dir(path)
+    }
+
+    fn read(&self, path: &str) -> VfsResult<Vec<u8>> {
+        self.tree.read(path)
+    }
+
+    fn write(&mut self, _path: &str, _contents: &[u8], _append: bool) -> VfsResult<()> {
+        Err(VfsError::ReadOnly)
+    }
+
+    fn touch(&mut self, _path: &str) -> VfsResult<()> {
+        Err(VfsError::ReadOnly)
+    }
+
+    fn create_dir(&mut self, _path: &str) -> VfsResult<()> {
+        Err(VfsError::ReadOnly)
+    }
+
+    fn remove_dir(&mut self, _path: &str) -> VfsResult<()> {
+        Err(VfsError::ReadOnly)
+    }
+
+    fn remove(&mut self, _path: &str, _recursive: bool) -> VfsResult<()> {
+        Err(VfsError::ReadOnly)
+    }
+
+    fn copy(&mut self, _from: &str, _to: &str, _recursive: bool) -> VfsResult<()> {
+        Err(VfsError::ReadOnly)
+    }
+
+    fn rename(&mut self, _from: &str, _to: &str) -> VfsResult<()> {
+        Err(VfsError::ReadOnly)
+    }
+
+    fn read_only(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a ustar header followed by the padded contents
+    fn entry(name: &str, kind: u8, contents: &[u8]) -> Vec<u8> {
+        let mut header = vec![0; 512];
+        header[..name.len()].copy_from_slice(name.as_bytes());
+        header[100..107].copy_from_slice(b"0000640");
+        header[124..135].copy_from_slice(format!("{:011o}", contents.len()).as_bytes());
+        header[136..147].copy_from_slice(b"14524770400");
+        header[148..156].copy_from_slice(b"        ");
+        header[156] = kind;
+        header[257..263].copy_from_slice(b"ustar\0");
+        seal(&mut header);
+        header.extend_from_slice(contents);
+        header.resize(512 + contents.len().div_ceil(512) * 512, 0);
+        header
+    }
+
+    // fills in the header's checksum
+    fn seal(header: &mut [u8]) {
+        header[148..156].copy_from_slice(b"        ");
+        let checksum: u32 = header[..512].iter().map(|&byte| byte as u32).sum();
+        header[148..155].copy_from_slice(format!("{:06o}\0", checksum).as_bytes());
+    }
+
+    #[test]
+    fn tar_archives_are_read_only_trees() {
+        let mut archive = entry("docs/", b'5', b"");
+        archive.extend(entry("docs/readme.txt", b'0', b"hello"));
+        archive.extend(entry("docs/link", b'2', b""));
+        archive.extend(vec![0; 1024]);
+
+        let mut fs = ReadOnlyFs::from_tar(&archive, "guest").unwrap();
+        assert_eq!(fs.read("/docs/readme.txt").unwrap(), b"hello");
+        let meta = fs.metadata("/docs/readme.txt").unwrap();
+        assert_eq!((meta.mode_string().as_str(), meta.mtime), ("-rw-r-----", 1_700_000_000_000.0));
+        assert_eq!(fs.read_dir("/docs").unwrap().len(), 1);
+        assert_eq!(fs.write("/docs/new", b"", false), Err(VfsError::ReadOnly));
+        assert_eq!(fs.remove("/docs", true), Err(VfsError::ReadOnly));
+
+        assert!(ReadOnlyFs::from_tar(&archive[..600], "guest").is_err());
+        assert!(ReadOnlyFs::from_tar(&archive[..1026], "guest").is_err());
+        assert!(ReadOnlyFs::from_tar(&[1; 512], "guest").is_err());
+
+        // a size far past the end of the archive, up to what the field holds
+        let mut huge = entry("huge", b'0', b"");
+        huge[124..136].copy_from_slice(b"777777777777");
+        seal(&mut huge);
+        assert_eq!(ReadOnlyFs::from_tar(&huge, "guest").err().as_deref(), Some("truncated archive"));
+    }
+}
@@ -0,0 +1,792 @@
+use crate::vt::{extended_color, palette, Action, Parser};
+use std::collections::BTreeSet;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Color(pub u8, pub u8, pub u8);
+
+impl Color {
+    pub const WHITE: Color = Color(0xFF, 0xFF, 0xFF);
+    pub const BLACK: Color = Color(0x00, 0x00, 0x00);
+
+    // parses "#RRGGBB"
+    pub fn from_hex(hex: &str) -> Option<Color> {
+        let hex = hex.strip_prefix('#')?;
+        if hex.len() != 6 || !hex.is_ascii() {
+            return None;
+        }
+        let channel = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16).ok();
+        Some(Color(channel(0)?, channel(2)?, channel(4)?))
+    }
+
+    pub fn to_css(self) -> String {
+        format!("#{:02X}{:02X}{:02X}", self.0, self.1, self.2)
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Style {
+    pub fg: Color,
+    // None draws the canvas background
+    pub bg: Option<Color>,
+    pub bold: bool,
+    pub dim: bool,
+    pub italic: bool,
+    pub underline: bool,
+    pub strikethrough: bool,
+    pub inverse: bool,
+}
+
+impl Default for Style {
+    fn default() -> Self {
+        Style {
+            fg: Color::WHITE,
+            bg: None,
+            bold: false,
+            dim: false,
+            italic: false,
+            underline: false,
+            strikethrough: false,
+            inverse: false,
+        }
+    }
+}
+
+impl Style {
+    // the foreground and background actually drawn, after inverse and dim
+    pub fn colors(&self) -> (Color, Color) {
+        let (mut fg, bg) = match self.inverse {
+            true => (self.bg.unwrap_or(Color::BLACK), self.fg),
+            false => (self.fg, self.bg.unwrap_or(Color::BLACK)),
+        };
+        if self.dim {
+            let mix = |a: u8, b: u8| ((a as u16 + b as u16) / 2) as u8;
+            fg = Color(mix(fg.0, bg.0), mix(fg.1, bg.1), mix(fg.2, bg.2));
+        }
+        (fg, bg)
+    }
+
+    // Applies a markup attribute letter: b(old), d(im), i(talic), u(nderline),
+    // s(trikethrough), r(everse) or 0 to reset everything.
+    fn apply_markup(&mut self, attribute: char) -> bool {
+        match attribute {
+            'b' => self.bold = true,
+            'd' => self.dim = true,
+            'i' => self.italic = true,
+            'u' => self.underline = true,
+            's' => self.strikethrough = true,
+            'r' => self.inverse = true,
+            '0' => *self = Style::default(),
+            _ => return false,
+        }
+        true
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Cell {
+    pub ch: char,
+    pub style: Style,
+}
+
+impl Default for Cell {
+    fn default() -> Self {
+        Cell { ch: ' ', style: Style::default() }
+    }
+}
+
+#[derive(Clone, Default, Debug)]
+pub struct Row {
+    pub cells: Vec<Cell>,
+    // true when the logical line continues on the next row
+    pub wrapped: bool,
+}
+
+pub const DEFAULT_SCROLLBACK: usize = 1000;
+
+// A grid of character cells. Positions are (line, column) pairs counted from
+// the top of the buffer; nothing here knows about pixels or the canvas.
+// The buffer keeps up to `scrollback` lines above the visible viewport.
+pub struct Screen {
+    cols: usize,
+    rows: usize,
+    lines: Vec<Row>,
+    cursor: (usize, usize),
+    lock: Option<(usize, usize)>,
+    // where the current prompt was drawn
+    prompt_start: Option<(usize, usize)>,
+    style: Style,
+    parser: Parser,
+    saved_cursor: Option<(usize, usize)>,
+    title: Option<String>,
+    scrollback: usize,
+    // how many lines the viewport is scrolled up from the bottom
+    scroll_offset: usize,
+    dirty: BTreeSet<usize>,
+    full_redraw: bool,
+    rendered_top: usize,
+    cursor_visible: bool,
+    rendered_cursor: (usize, usize),
+}
+
+impl Screen {
+    pub fn new(cols: usize, rows: usize) -> Screen {
+        Screen {
+            cols: cols.max(1),
+            rows: rows.max(1),
+            lines: vec![Row::default()],
+            cursor: (0, 0),
+            lock: None,
+            prompt_start: None,
+            style: Style::default(),
+            parser: Parser::new(),
+            saved_cursor: None,
+            title: None,
+            scrollback: DEFAULT_SCROLLBACK,
+            scroll_offset: 0,
+            dirty: BTreeSet::new(),
+            full_redraw: true,
+            rendered_top: 0,
+            cursor_visible: true,
+            rendered_cursor: (0, 0),
+        }
+    }
+
+    pub fn rows(&self) -> usize {
+        self.rows
+    }
+
+    // Changes the grid size, re-wrapping every logical line to the new width.
+    // The cursor, the lock and the other remembered positions keep pointing
+    // at the same characters.
+    pub fn resize(&mut self, cols: usize, rows: usize) {
+        let cols = cols.max(1);
+        if cols != self.cols {
+            self.reflow(cols);
+        }
+        self.rows = rows.max(1);
+        self.trim_scrollback();
+        self.scroll_offset = self.scroll_offset.min(self.max_scroll());
+        self.full_redraw = true;
+    }
+
+    fn reflow(&mut self, cols: usize) {
+        let old_cols = self.cols;
+        let mut tracked = [Some(self.cursor), self.lock, self.prompt_start, self.saved_cursor];
+        let mut logical: Vec<Vec<Cell>> = Vec::new();
+        let mut continued = false;
+        for (i, row) in self.lines.iter().enumerate() {
+            if !continued {
+                logical.push(Vec::new());
+            }
+            let index = logical.len() - 1;
+            let current = &mut logical[index];
+            let start = current.len();
+            // positions become (logical line, offset into it) for now
+            for (line, col) in tracked.iter_mut().flatten() {
+                if *line == i {
+                    *line = index;
+                    *col += start;
+                }
+            }
+            current.extend_from_slice(&row.cells);
+            if row.wrapped {
+                current.resize(start + old_cols, Cell::default());
+            }
+            continued = row.wrapped;
+        }
+
+        let mut lines = Vec::new();
+        let mut placed = [None; 4];
+        for (i, cells) in logical.into_iter().enumerate() {
+            let first = lines.len();
+            let needed = tracked
+                .iter()
+                .flatten()
+                .filter(|(line, _)| *line == i)
+                .map(|(_, offset)| *offset)
+                .fold(cells.len(), usize::max);
+            let count = needed.div_ceil(cols).max(1);
+            for chunk in 0..count {
+                let end = cells.len().min((chunk + 1) * cols);
+                let start = (chunk * cols).min(end);
+                lines.push(Row { cells: cells[start..end].to_vec(), wrapped: chunk + 1 < count });
+            }
+            for (position, new) in tracked.iter().zip(placed.iter_mut()) {
+                let Some((line, offset)) = *position else { continue };
+                if line != i {
+                    continue;
+                }
+                *new = Some(if offset > 0 && offset % cols == 0 && offset / cols == count {
+                    // end of a full row, before the wrap happens
+                    (first + count - 1, cols)
+                } else {
+                    (first + offset / cols, offset % cols)
+                });
+            }
+        }
+
+        self.lines = lines;
+        self.cursor = placed[0].unwrap_or_default();
+        self.lock = placed[1];
+        self.prompt_start = placed[2];
+        self.saved_cursor = placed[3];
+        self.cols = cols;
+        self.dirty.clear();
+    }
+
+    pub fn set_scrollback(&mut self, lines: usize) {
+        self.scrollback = lines;
+        self.trim_scrollback();
+    }
+
+    pub fn line(&self, line: usize) -> Option<&Row> {
+        self.lines.get(line)
+    }
+
+    pub fn cursor(&self) -> (usize, usize) {
+        self.cursor
+    }
+
+    pub fn lock(&self) -> Option<(usize, usize)> {
+        self.lock
+    }
+
+    pub fn lock_here(&mut self) {
+        self.lock = Some(self.cursor);
+    }
+
+    pub fn mark_prompt(&mut self) {
+        self.prompt_start = Some(self.cursor);
+    }
+
+    pub fn prompt_start(&self) -> Option<(usize, usize)> {
+        self.prompt_start
+    }
+
+    pub fn reset_style(&mut self) {
+        self.style = Style::default();
+    }
+
+    // the window title most recently set with an OSC sequence, if it changed
+    pub fn take_title(&mut self) -> Option<String> {
+        self.title.take()
+    }
+
+    // Writes text at the cursor, interpreting ANSI escape sequences and the
+    // Buudunn markup, which changes the style for the rest of the text:
+    //   \#RRGGBB  foreground color
+    //   \%RRGGBB  background color
+    //   \*b \*d \*i \*u \*s \*r  bold, dim, italic, underline, strikethrough, inverse
+    //   \*0  reset to the default style
+    pub fn write(&mut self, text: &str) {
+        let chars: Vec<char> = text.chars().collect();
+        let mut i = 0;
+        while i < chars.len() {
+            let ch = chars[i];
+            if ch == '\\' && self.parser.is_ground() {
+                match chars.get(i + 1) {
+                    Some('#') => {
+                        let hex: String = chars.iter().skip(i + 1).take(7).collect();
+                        if let Some(color) = Color::from_hex(&hex) {
+                            self.style.fg = color;
+                        }
+                        i += 8;
+                        continue;
+                    }
+                    Some('%') => {
+                        let hex: String = chars.iter().skip(i + 2).take(6).collect();
+                        if let Some(color) = Color::from_hex(&format!("#{}", hex)) {
+                            self.style.bg = Some(color);
+                            i += 8;
+                            continue;
+                        }
+                    }
+                    Some('*') => {
+                        if let Some(&attribute) = chars.get(i + 2) {
+                            if self.style.apply_markup(attribute) {
+                                i += 3;
+                                continue;
+                            }
+                        }
+                    }
+                    _ => {}
+                }
+            }
+            if let Some(action) = self.parser.advance(ch) {
+                self.apply(action);
+            }
+            i += 1;
+        }
+    }
+
+    fn apply(&mut self, action: Action) {
+        match action {
+            Action::Print(ch) => self.put(ch),
+            Action::Execute('\n' | '\x0b' | '\x0c') => self.newline(),
+            Action::Execute('\r') => self.cursor.1 = 0,
+            Action::Execute('\x08') => {
+                self.cursor.1 = self.cursor.1.min(self.cols - 1).saturating_sub(1);
+            }
+            Action::Execute('\t') => {
+                self.cursor.1 = ((self.cursor.1 / 8 + 1) * 8).min(self.cols - 1);
+            }
+            Action::Esc('7') => self.saved_cursor = Some(self.cursor),
+            Action::Esc('8') => {
+                if let Some((line, col)) = self.saved_cursor {
+                    self.move_to(line, col);
+                }
+            }
+            Action::Esc('D') => {
+                let col = self.cursor.1;
+                self.newline();
+                self.cursor.1 = col;
+            }
+            Action::Esc('E') => self.newline(),
+            Action::Esc('M') => {
+                let line = self.cursor.0.saturating_sub(1).max(self.home_line());
+                self.move_to(line, self.cursor.1);
+            }
+            Action::Esc('c') => {
+                self.erase_display(2);
+                self.style = Style::default();
+                let home = self.home_line();
+                self.move_to(home, 0);
+            }
+            Action::Csi { private: None, params, intermediates, action } if intermediates.is_empty() => {
+                self.csi(action, &params);
+            }
+            Action::Csi { private: Some('?'), params, action: action @ ('h' | 'l'), .. }
+                if params.contains(&25) => {
+                    self.cursor_visible = action == 'h';
+                    self.dirty.insert(self.cursor.0);
+                }
+            Action::Osc(parts) => {
+                if matches!(parts[0].as_str(), "0" | "2") {
+                    self.title = Some(parts[1..].join(";"));
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn csi(&mut self, action: char, params: &[u16]) {
+        // missing and zero parameters both mean "use the default"
+        let arg = |i: usize, default: usize| match params.get(i) {
+            Some(&0) | None => default,
+            Some(&n) => n as usize,
+        };
+        let home = self.home_line();
+        let bottom = home + self.rows - 1;
+        let (line, col) = self.cursor;
+        let col = col.min(self.cols - 1);
+        let last_col = self.cols - 1;
+        match action {
+            'A' => self.move_to(line.saturating_sub(arg(0, 1)).max(home), col),
+            'B' | 'e' => self.move_to((line + arg(0, 1)).min(bottom), col),
+            'C' | 'a' => self.move_to(line, (col + arg(0, 1)).min(last_col)),
+            'D' => self.move_to(line, col.saturating_sub(arg(0, 1))),
+            'E' => self.move_to((line + arg(0, 1)).min(bottom), 0),
+            'F' => self.move_to(line.saturating_sub(arg(0, 1)).max(home), 0),
+            'G' | '`' => self.move_to(line, (arg(0, 1) - 1).min(last_col)),
+            'H' | 'f' => self.move_to(home + (arg(0, 1) - 1).min(self.rows - 1), (arg(1, 1) - 1).min(last_col)),
+            'd' => self.move_to(home + (arg(0, 1) - 1).min(self.rows - 1), col),
+            'J' => self.erase_display(arg(0, 0)),
+            'K' => self.erase_line(arg(0, 0)),
+            'm' => self.sgr(params),
+            's' => self.saved_cursor = Some(self.cursor),
+            'u' => {
+                if let Some((line, col)) = self.saved_cursor {
+                    self.move_to(line, col);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn sgr(&mut self, params: &[u16]) {
+        if params.is_empty() {
+            self.style = Style::default();
+            return;
+        }
+        let mut i = 0;
+        while i < params.len() {
+            match params[i] {
+                0 => self.style = Style::default(),
+                1 => self.style.bold = true,
+                2 => self.style.dim = true,
+                3 => self.style.italic = true,
+                4 | 21 => self.style.underline = true,
+                7 => self.style.inverse = true,
+                9 => self.style.strikethrough = true,
+                22 => {
+                    self.style.bold = false;
+                    self.style.dim = false;
+                }
+                23 => self.style.italic = false,
+                24 => self.style.underline = false,
+                27 => self.style.inverse = false,
+                29 => self.style.strikethrough = false,
+                n @ 30..=37 => self.style.fg = palette(n as u8 - 30),
+                n @ 90..=97 => self.style.fg = palette(n as u8 - 90 + 8),
+                39 => self.style.fg = Style::default().fg,
+                n @ 40..=47 => self.style.bg = Some(palette(n as u8 - 40)),
+                n @ 100..=107 => self.style.bg = Some(palette(n as u8 - 100 + 8)),
+                49 => self.style.bg = None,
+                n @ (38 | 48) => {
+                    let (color, used) = extended_color(&params[i + 1..]);
+                    match (n, color) {
+                        (38, Some(color)) => self.style.fg = color,
+                        (48, Some(color)) => self.style.bg = Some(color),
+                        _ => {}
+                    }
+                    i += used;
+                }
+                _ => {}
+            }
+            i += 1;
+        }
+    }
+
+    // first buffer line of the screen that cursor addressing counts from
+    fn home_line(&self) -> usize {
+        self.lines.len().saturating_sub(self.rows)
+    }
+
+    fn move_to(&mut self, line: usize, col: usize) {
+        self.ensure_line(line);
+        self.cursor = (line, col);
+    }
+
+    fn erase_line(&mut self, mode: usize) {
+        let (line, col) = self.cursor;
+        let row = &mut self.lines[line];
+        match mode {
+            0 => {
+                row.cells.truncate(col);
+                row.wrapped = false;
+            }
+            1 => {
+                let end = (col + 1).min(row.cells.len());
+                row.cells[..end].fill(Cell::default());
+            }
+            2 => {
+                row.cells.clear();
+                row.wrapped = false;
+            }
+            _ => return,
+        }
+        self.dirty.insert(line);
+    }
+
+    fn erase_display(&mut self, mode: usize) {
+        let home = self.home_line();
+        let line = self.cursor.0;
+        let range = match mode {
+            0 => {
+                self.erase_line(0);
+                line + 1..self.lines.len()
+            }
+            1 => {
+                self.erase_line(1);
+                home..line
+            }
+            2 => home..self.lines.len(),
+            3 => {
+                // drop the scrollback above the screen
+                self.drop_lines(home);
+                return;
+            }
+            _ => return,
+        };
+        for i in range {
+            self.lines[i] = Row::default();
+            self.dirty.insert(i);
+        }
+    }
+
+    pub fn newline(&mut self) {
+        self.cursor = (self.cursor.0 + 1, 0);
+        self.ensure_line(self.cursor.0);
+        self.trim_scrollback();
+        self.scroll_to_bottom();
+    }
+
+    pub fn put(&mut self, ch: char) {
+        self.scroll_to_bottom();
+        if self.cursor.1 >= self.cols {
+            self.lines[self.cursor.0].wrapped = true;
+            self.newline();
+        }
+        let (line, col) = self.cursor;
+        let row = &mut self.lines[line];
+        if row.cells.len() <= col {
+            row.cells.resize(col + 1, Cell::default());
+        }
+        row.cells[col] = Cell { ch, style: self.style };
+        self.cursor.1 += 1;
+        self.dirty.insert(line);
+    }
+
+    // Writes text without interpreting markup or escape sequences.
+    pub fn write_plain(&mut self, text: &str) {
+        for ch in text.chars() {
+            if ch == '\n' {
+                self.newline();
+            } else {
+                self.put(ch);
+            }
+        }
+    }
+
+    // where the cursor would end up after writing plain text from `position`
+    pub fn advance(&self, (mut line, mut col): (usize, usize), text: &str) -> (usize, usize) {
+        for ch in text.chars() {
+            if ch == '\n' || col >= self.cols {
+                line += 1;
+                col = 0;
+            }
+            if ch != '\n' {
+                col += 1;
+            }
+        }
+        (line, col)
+    }
+
+    // Erases everything from `position` to the end of the buffer.
+    pub fn clear_from(&mut self, (line, col): (usize, usize)) {
+        if line >= self.lines.len() {
+            return;
+        }
+        let row = &mut self.lines[line];
+        row.cells.truncate(col);
+        row.wrapped = false;
+        self.dirty.extend(line..self.lines.len());
+        self.lines.truncate(line + 1);
+        self.scroll_offset = self.scroll_offset.min(self.max_scroll());
+        if self.cursor.0 > line || (self.cursor.0 == line && self.cursor.1 > col) {
+            self.cursor = (line, col);
+        }
+    }
+
+    pub fn set_cursor(&mut self, (line, col): (usize, usize)) {
+        self.scroll_to_bottom();
+        self.move_to(line, col);
+    }
+
+    pub fn cursor_visible(&self) -> bool {
+        self.cursor_visible
+    }
+
+    fn ensure_line(&mut self, line: usize) {
+        while self.lines.len() <= line {
+            self.lines.push(Row::default());
+            self.dirty.insert(self.lines.len() - 1);
+        }
+    }
+
+    // drops the oldest lines once the history grows past the limit
+    fn trim_scrollback(&mut self) {
+        let excess = self.lines.len().saturating_sub(self.scrollback + self.rows);
+        if excess == 0 {
+            return;
+        }
+        self.drop_lines(excess);
+    }
+
+    // removes lines from the top of the buffer, moving positions up to match
+    fn drop_lines(&mut self, count: usize) {
+        self.lines.drain(..count);
+        let shift = |(line, col): (usize, usize)| (line.saturating_sub(count), col);
+        self.cursor = shift(self.cursor);
+        self.rendered_cursor = shift(self.rendered_cursor);
+        self.lock = self.lock.map(shift);
+        self.prompt_start = self.prompt_start.map(shift);
+        self.saved_cursor = self.saved_cursor.map(shift);
+        self.scroll_offset = self.scroll_offset.min(self.max_scroll());
+        self.dirty.clear();
+        self.full_redraw = true;
+    }
+
+    fn max_scroll(&self) -> usize {
+        self.lines.len().saturating_sub(self.rows)
+    }
+
+    // first buffer line shown in the viewport
+    pub fn viewport_top(&self) -> usize {
+        self.max_scroll() - self.scroll_offset
+    }
+
+    // positive amounts scroll towards older output
+    pub fn scroll(&mut self, lines: isize) {
+        let offset = if lines >= 0 {
+            self.scroll_offset.saturating_add(lines as usize).min(self.max_scroll())
+        } else {
+            self.scroll_offset.saturating_sub(lines.unsigned_abs())
+        };
+        if offset != self.scroll_offset {
+            self.scroll_offset = offset;
+            self.full_redraw = true;
+        }
+    }
+
+    pub fn scroll_to_bottom(&mut self) {
+        if self.scroll_offset > 0 {
+            self.scroll_offset = 0;
+            self.full_redraw = true;
+        }
+    }
+
+    // Viewport rows that changed since the last call, for the renderer. Every
+    // row is returned when the viewport itself moved.
+    pub fn take_dirty(&mut self) -> Vec<usize> {
+        // the rows the cursor left and moved onto need redrawing too
+        self.dirty.insert(self.rendered_cursor.0);
+        self.dirty.insert(self.cursor.0);
+        self.rendered_cursor = self.cursor;
+        let top = self.viewport_top();
+        let dirty = std::mem::take(&mut self.dirty);
+        if self.full_redraw || top != self.rendered_top {
+            self.full_redraw = false;
+            self.rendered_top = top;
+            return (0..self.rows).collect();
+        }
+        dirty
+            .into_iter()
+            .filter(|line| (top..top + self.rows).contains(line))
+            .map(|line| line - top)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    impl Screen {
+        // the characters of a single row, trailing blanks removed
+        fn line_text(&self, line: usize) -> String {
+            self.lines
+                .get(line)
+                .map(|row| row.cells.iter().map(|cell| cell.ch).collect::<String>().trim_end().to_string())
+                .unwrap_or_default()
+        }
+    }
+
+    #[test]
+    fn wraps_at_column_limit() {
+        let mut screen = Screen::new(4, 10);
+        screen.write("abcdef");
+        assert_eq!(screen.line_text(0), "abcd");
+        assert_eq!(screen.line_text(1), "ef");
+        assert!(screen.line(0).unwrap().wrapped);
+        assert_eq!(screen.cursor(), (1, 2));
+    }
+
+    #[test]
+    fn markup_sets_foreground() {
+        let mut screen = Screen::new(20, 10);
+        screen.write("a\\#FF0000b");
+        let row = screen.line(0).unwrap();
+        assert_eq!(screen.line_text(0), "ab");
+        assert_eq!(row.cells[0].style.fg, Color::WHITE);
+        assert_eq!(row.cells[1].style.fg, Color(0xFF, 0, 0));
+    }
+
+    #[test]
+    fn input_after_lock_can_be_redrawn() {
+        let mut screen = Screen::new(4, 10);
+        screen.write("$ ab");
+        screen.lock_here();
+        screen.write_plain("cdefg");
+        assert_eq!(screen.line_text(2), "g");
+        let lock = screen.lock().unwrap();
+        screen.clear_from(lock);
+        screen.write_plain("x\\#FF0000");
+        screen.set_cursor(screen.advance(lock, "x"));
+        assert_eq!(screen.line_text(0), "$ ab");
+        assert_eq!(screen.line_text(1), "x\\#F");
+        assert_eq!(screen.line_text(2), "F000");
+        assert_eq!(screen.line_text(3), "0");
+        assert_eq!(screen.cursor(), (1, 1));
+    }
+
+    #[test]
+    fn ansi_cursor_movement_and_erase() {
+        let mut screen = Screen::new(20, 5);
+        screen.write("hello\nworld");
+        screen.write("\x1b[1;2H\x1b[K");
+        assert_eq!(screen.line_text(0), "h");
+        screen.write("\x1b[2;3H\x1b[1K!");
+        assert_eq!(screen.line_text(1), "  !ld");
+        screen.write("\x1b[2J");
+        assert_eq!(screen.line_text(0), "");
+        assert_eq!(screen.line_text(1), "");
+    }
+
+    #[test]
+    fn sgr_colors_and_title() {
+        let mut screen = Screen::new(20, 5);
+        screen.write("\x1b[31ma\x1b[38;5;21mb\x1b[38;2;1;2;3mc\x1b[0md\x1b]2;buudunn\x07");
+        let fg: Vec<Color> = screen.line(0).unwrap().cells.iter().map(|cell| cell.style.fg).collect();
+        assert_eq!(fg, vec![palette(1), palette(21), Color(1, 2, 3), Color::WHITE]);
+        assert_eq!(screen.take_title(), Some("buudunn".to_string()));
+    }
+
+    #[test]
+    fn attributes_from_markup_and_sgr() {
+        let mut screen = Screen::new(20, 5);
+        screen.write("\\*b\\%102030a\\*0b\x1b[1;3;4;7;9;44mc\x1b[22;23;24;27;29;49md");
+        let cells = &screen.line(0).unwrap().cells;
+        assert!(cells[0].style.bold);
+        assert_eq!(cells[0].style.bg, Some(Color(0x10, 0x20, 0x30)));
+        assert_eq!(cells[1].style, Style::default());
+        let style = cells[2].style;
+        assert!(style.bold && style.italic && style.underline && style.inverse && style.strikethrough);
+        assert_eq!(style.colors(), (palette(4), Color::WHITE));
+        assert_eq!(cells[3].style, Style::default());
+    }
+
+    #[test]
+    fn resize_reflows_wrapped_lines() {
+        let mut screen = Screen::new(4, 10);
+        screen.write("$ abcdef\nxy");
+        screen.resize(8, 10);
+        assert_eq!(screen.line_text(0), "$ abcdef");
+        assert_eq!(screen.line_text(1), "xy");
+        assert_eq!(screen.cursor(), (1, 2));
+        screen.resize(3, 10);
+        assert_eq!(screen.line_text(0), "$ a");
+        assert_eq!(screen.line_text(2), "ef");
+        assert_eq!(screen.line_text(3), "xy");
+        assert_eq!(screen.cursor(), (3, 2));
+    }
+
+    #[test]
+    fn resize_keeps_lock_on_the_same_character() {
+        let mut screen = Screen::new(4, 10);
+        screen.write("$ ");
+        screen.lock_here();
+        screen.write("abcd");
+        screen.resize(2, 10);
+        assert_eq!(screen.line_text(1), "ab");
+        assert_eq!(screen.lock(), Some((1, 0)));
+        assert_eq!(screen.cursor(), (2, 2));
+        screen.resize(3, 10);
+        assert_eq!(screen.lock(), Some((0, 2)));
+        assert_eq!(screen.cursor(), (1, 3));
+    }
+
+    #[test]
+    fn scrollback_is_bounded_and_output_snaps_to_bottom() {
+        let mut screen = Screen::new(10, 3);
+        screen.set_scrollback(2);
+        for i in 0..10 {
+            screen.write(&format!("{}\n", i));
+        }
+        assert_eq!(screen.line_text(0), "6");
+        screen.scroll(100);
+        assert_eq!(screen.viewport_top(), 0);
+        screen.write("x");
+        assert_eq!(screen.viewport_top(), 2);
+    }
+}
@@ -0,0 +1,499 @@
+use crate::args::{Arg, ArgKind, Args, Spec};
+use crate::cmd::{register_cmd, run_list, run_stage, CmdFuture, Command, Status, EXIT_FAILURE, EXIT_NOT_EXECUTABLE, EXIT_NOT_FOUND, EXIT_SUCCESS, EXIT_USAGE, LAST_STATUS};
+use crate::env::{is_valid_name, split_assignment, ENV};
+use crate::io::Io;
+use crate::parser::{parse, Stage};
+use crate::vfs::{absolute, FileSystem, VfsError, VFS};
+use once_cell::sync::Lazy;
+use std::{collections::HashMap, sync::Arc, sync::Mutex};
+
+// how deep functions and scripts can call each other
+const MAX_DEPTH: usize = 100;
+
+// A running function or script.
+struct Frame {
+    // `$0`: the script's name, which functions keep
+    name: String,
+    // `$1` onwards
+    args: Vec<String>,
+    // variables made `local`, with the values to put back afterwards
+    locals: Vec<(String, Option<String>)>,
+    // set by `return`, so everything up to the frame stops running
+    returning: bool,
+}
+
+static FRAMES: Lazy<Mutex<Vec<Frame>>> = Lazy::new(|| Mutex::new(Vec::new()));
+
+static FUNCTIONS: Lazy<Mutex<HashMap<String, Arc<Stage>>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
+// forgets every function and anything still running
+#[cfg(test)]
+pub fn reset() {
+    FRAMES.lock().unwrap().clear();
+    FUNCTIONS.lock().unwrap().clear();
+}
+
+pub fn init_script_cmds() {
+    register_cmd(Sh);
+    register_cmd(Return);
+    register_cmd(Local);
+    register_cmd(Shift);
+    register_cmd(True);
+    register_cmd(False);
+    register_cmd(Test);
+    register_cmd(Bracket);
+}
+
+pub fn define_function(name: &str, body: Arc<Stage>) {
+    FUNCTIONS.lock().unwrap().insert(name.to_string(), body);
+}
+
+pub fn get_function(name: &str) -> Option<Arc<Stage>> {
+    FUNCTIONS.lock().unwrap().get(name).cloned()
+}
+
+// `$0`
+pub fn script_name() -> String {
+    FRAMES.lock().unwrap().last().map(|frame| frame.name.clone()).unwrap_or_else(|| "buudunn".to_string())
+}
+
+// `$1` onwards, of the innermost function or script
+pub fn positional_args() -> Vec<String> {
+    FRAMES.lock().unwrap().last().map(|frame| frame.args.clone()).unwrap_or_default()
+}
+
+// whether `return` was used and the commands after it should be skipped
+pub fn returning() -> bool {
+    FRAMES.lock().unwrap().last().is_some_and(|frame| frame.returning)
+}
+
+fn enter(name: String, args: Vec<String>) -> bool {
+    let mut frames = FRAMES.lock().unwrap();
+    if frames.len() >= MAX_DEPTH {
+        return false;
+    }
+    frames.push(Frame { name, args, locals: Vec::new(), returning: false });
+    true
+}
+
+fn leave() {
+    let frame = FRAMES.lock().unwrap().pop();
+    let mut env = ENV.lock().unwrap();
+    for (name, old) in frame.map(|frame| frame.locals).unwrap_or_default().into_iter().rev() {
+        match old {
+            Some(old) => env.set(&name, &old),
+            None => env.unset(&name),
+        }
+    }
+}
+
+// Runs a function with `args` as its positional parameters.
+pub async fn call_function(name: &str, body: &Stage, args: Vec<String>, io: &mut Io) -> Status {
+    if !enter(script_name(), args) {
+        io.error(&format!("buudunn: {}: maximum function nesting level exceeded ({})", name, MAX_DEPTH));
+        return EXIT_FAILURE;
+    }
+    let status = run_stage(body, io).await;
+    leave();
+    status
+}
+
+// Runs the script at `path` for `./script.sh`, which has to say in its
+// first line that it is a shell script.
+pub async fn run_file(path: &str, args: Vec<String>, io: &mut Io) -> Status {
+    let source = match read_script(path) {
+        Ok(source) => source,
+        Err(err) => {
+            io.error(&format!("buudunn: {}: {}", path, err));
+            return match err {
+                VfsError::NotFound => EXIT_NOT_FOUND,
+                _ => EXIT_NOT_EXECUTABLE,
+            };
+        }
+    };
+    let shebang = source.lines().next().and_then(|line| line.strip_prefix("#!")).map(str::trim);
+    let interpreter = shebang.map(|shebang| {
+        let mut words = shebang.split_whitespace();
+        let program = words.next().unwrap_or_default();
+        // `#!/usr/bin/env sh` names the shell after env
+        match program.rsplit('/').next() {
+            Some("env") => words.next().unwrap_or_default(),
+            _ => program,
+        }
+    });
+    match interpreter.map(|program| program.rsplit('/').next().unwrap_or_default()) {
+        Some("sh" | "bash" | "buudunn") => run_source(path, &source, args, io).await,
+        Some(_) => {
+            io.error(&format!("buudunn: {}: {}: bad interpreter", path, shebang.unwrap_or_default()));
+            EXIT_NOT_EXECUTABLE
+        }
+        None => {
+            io.error(&format!("buudunn: {}: cannot execute: no #! line", path));
+            EXIT_NOT_EXECUTABLE
+        }
+    }
+}
+
+fn read_script(path: &str) -> Result<String, VfsError> {
+    let path = absolute(path);
+    let contents = VFS.lock().unwrap().read(&path)?;
+    Ok(String::from_utf8_lossy(&contents).to_string())
+}
+
+// Runs `source` as the script `name`. Syntax errors say which line they
+// are on, and nothing runs if there is one.
+async fn run_source(name: &str, source: &str, args: Vec<String>, io: &mut Io) -> Status {
+    let list = match parse(source) {
+        Ok(list) => list,
+        Err(err) => {
+            let line = source[..err.pos.min(source.len())].matches('\n').count() + 1;
+            io.error(&format!("{}: line {}: {}\n{}", name, line, err, err.caret(source)));
+            return EXIT_USAGE;
+        }
+    };
+    if !enter(name.to_string(), args) {
+        io.error(&format!("buudunn: {}: maximum function nesting level exceeded ({})", name, MAX_DEPTH));
+        return EXIT_FAILURE;
+    }
+    let status = run_list(&list, io).await;
+    leave();
+    status
+}
+
+struct Sh;
+
+impl Command for Sh {
+    fn name(&self) -> &'static str { "sh" }
+    fn summary(&self) -> &'static str { "Runs a shell script from a file, or the commands piped in." }
+
+    fn spec(&self) -> Spec {
+        Spec::new()
+            .arg(Arg::new("script", ArgKind::Path).optional())
+            .arg(Arg::new("argument", ArgKind::Text).optional().variadic())
+    }
+
+    fn run<'a>(&'a self, args: Args, io: &'a mut Io) -> CmdFuture<'a> {
+        Box::pin(sh(args, io))
+    }
+}
+
+async fn sh(args: Args, io: &mut Io) -> Status {
+    let arguments = args.strs("argument").into_iter().map(str::to_string).collect();
+    match args.str("script") {
+        Some(script) => match read_script(script) {
+            Ok(source) => run_source(script, &source, arguments, io).await,
+            Err(err) => {
+                io.error(&format!("sh: {}: {}", script, err));
+                EXIT_NOT_FOUND
+            }
+        },
+        None if !io.stdin.is_terminal() => {
+            let source = io.stdin.read_to_string();
+            run_source("sh", &source, arguments, io).await
+        }
+        None => {
+            io.error("No script given. Name a file or pipe commands in.");
+            EXIT_FAILURE
+        }
+    }
+}
+
+struct Return;
+
+impl Command for Return {
+    fn name(&self) -> &'static str { "return" }
+    fn summary(&self) -> &'static str { "Leaves a function or script, with the last status or the one given." }
+
+    fn spec(&self) -> Spec {
+        Spec::new().arg(Arg::new("status", ArgKind::Integer).optional())
+    }
+
+    fn run<'a>(&'a self, args: Args, io: &'a mut Io) -> CmdFuture<'a> {
+        Box::pin(return_(args, io))
+    }
+}
+
+async fn return_(args: Args, io: &mut Io) -> Status {
+    let status = match args.integer("status") {
+        Some(status) => (status & 0xff) as Status,
+        None => *LAST_STATUS.lock().unwrap(),
+    };
+    match FRAMES.lock().unwrap().last_mut() {
+        Some(frame) => frame.returning = true,
+        None => {
+            io.error("return: can only `return' from a function or script");
+            return EXIT_FAILURE;
+        }
+    }
+    status
+}
+
+struct Local;
+
+impl Command for Local {
+    fn name(&self) -> &'static str { "local" }
+    fn summary(&self) -> &'static str { "Sets variables that only last until the function returns." }
+
+    fn spec(&self) -> Spec {
+        Spec::new().arg(Arg::new("name[=value]", ArgKind::Variable).variadic())
+    }
+
+    fn run<'a>(&'a self, args: Args, io: &'a mut Io) -> CmdFuture<'a> {
+        Box::pin(local(args, io))
+    }
+}
+
+async fn local(args: Args, io: &mut Io) -> Status {
+    let mut frames = FRAMES.lock().unwrap();
+    let Some(frame) = frames.last_mut() else {
+        io.error("local: can only be used in a function");
+        return EXIT_FAILURE;
+    };
+    let mut env = ENV.lock().unwrap();
+    let mut status = EXIT_SUCCESS;
+    for word in args.strs("name[=value]") {
+        let (name, value) = match split_assignment(word) {
+            Some(assignment) => assignment,
+            None if is_valid_name(word) => (word, ""),
+            None => {
+                io.error(&format!("local: `{}': not a valid identifier", word));
+                status = EXIT_FAILURE;
+                continue;
+            }
+        };
+        if !frame.locals.iter().any(|(saved, _)| saved == name) {
+            frame.locals.push((name.to_string(), env.get(name).map(str::to_string)));
+        }
+        env.set(name, value);
+    }
+    status
+}
+
+struct Shift;
+
+impl Command for Shift {
+    fn name(&self) -> &'static str { "shift" }
+    fn summary(&self) -> &'static str { "Drops the first positional parameters, 1 unless told how many." }
+
+    fn spec(&self) -> Spec {
+        Spec::new().arg(Arg::new("count", ArgKind::Integer).optional())
+    }
+
+    fn run<'a>(&'a self, args: Args, io: &'a mut Io) -> CmdFuture<'a> {
+        Box::pin(shift(args, io))
+    }
+}
+
+async fn shift(args: Args, io: &mut Io) -> Status {
+    let count = args.integer("count").unwrap_or(1);
+    let mut frames = FRAMES.lock().unwrap();
+    let Some(frame) = frames.last_mut() else {
+        io.error("shift: can only be used in a function or script");
+        return EXIT_FAILURE;
+    };
+    match usize::try_from(count) {
+        Ok(count) if count <= frame.args.len() => {
+            frame.args.drain(..count);
+            EXIT_SUCCESS
+        }
+        _ => {
+            io.error(&format!("shift: {}: shift count out of range", count));
+            EXIT_FAILURE
+        }
+    }
+}
+
+struct True;
+
+impl Command for True {
+    fn name(&self) -> &'static str { "true" }
+    fn summary(&self) -> &'static str { "Does nothing, successfully." }
+
+    fn run<'a>(&'a self, _args: Args, _io: &'a mut Io) -> CmdFuture<'a> {
+        Box::pin(async { EXIT_SUCCESS })
+    }
+}
+
+struct False;
+
+impl Command for False {
+    fn name(&self) -> &'static str { "false" }
+    fn summary(&self) -> &'static str { "Does nothing, unsuccessfully." }
+
+    fn run<'a>(&'a self, _args: Args, _io: &'a mut Io) -> CmdFuture<'a> {
+        Box::pin(async { EXIT_FAILURE })
+    }
+}
+
+struct Test;
+
+impl Command for Test {
+    fn name(&self) -> &'static str { "test" }
+    fn summary(&self) -> &'static str { "Checks strings, numbers and files, for if and while." }
+
+    fn spec(&self) -> Spec {
+        Spec::new().arg(Arg::new("expression", ArgKind::Text).optional().variadic())
+    }
+
+    fn run<'a>(&'a self, args: Args, io: &'a mut Io) -> CmdFuture<'a> {
+        Box::pin(test("test", args, io))
+    }
+}
+
+// `[ expression ]`, the same as `test` with a closing bracket
+struct Bracket;
+
+impl Command for Bracket {
+    fn name(&self) -> &'static str { "[" }
+    fn summary(&self) -> &'static str { "Checks strings, numbers and files, like test." }
+
+    fn spec(&self) -> Spec {
+        Spec::new().arg(Arg::new("expression", ArgKind::Text).variadic())
+    }
+
+    fn run<'a>(&'a self, args: Args, io: &'a mut Io) -> CmdFuture<'a> {
+        Box::pin(test("[", args, io))
+    }
+}
+
+async fn test(name: &str, args: Args, io: &mut Io) -> Status {
+    let mut words = args.strs("expression");
+    if name == "[" && words.pop() != Some("]") {
+        io.error("[: missing `]'");
+        return EXIT_USAGE;
+    }
+    match evaluate(&words) {
+        Ok(true) => EXIT_SUCCESS,
+        Ok(false) => EXIT_FAILURE,
+        Err(err) => {
+            io.error(&format!("{}: {}", name, err));
+            EXIT_USAGE
+        }
+    }
+}
+
+// the operators `test` compares two words with
+const BINARY: &[&str] = &["=", "==", "!=", "-eq", "-ne", "-lt", "-le", "-gt", "-ge"];
+
+// A `test` expression. One word is true when it isn't empty.
+fn evaluate(words: &[&str]) -> Result<bool, String> {
+    match *words {
+        [] => Ok(false),
+        [word] => Ok(!word.is_empty()),
+        // going by the number of arguments like POSIX says, three are a
+        // comparison before a negation, so `test ! = !` compares
+        [left, operator, right] if BINARY.contains(&operator) => {
+            let number = |word: &str| word.trim().parse::<i64>().map_err(|_| format!("{}: integer expression expected", word));
+            match operator {
+                "=" | "==" => Ok(left == right),
+                "!=" => Ok(left != right),
+                "-eq" => Ok(number(left)? == number(right)?),
+                "-ne" => Ok(number(left)? != number(right)?),
+                "-lt" => Ok(number(left)? < number(right)?),
+                "-le" => Ok(number(left)? <= number(right)?),
+                "-gt" => Ok(number(left)? > number(right)?),
+                _ => Ok(number(left)? >= number(right)?),
+            }
+        }
+        ["!", ref rest @ ..] => evaluate(rest).map(|result| !result),
+        [operator, operand] => {
+            let file = || VFS.lock().unwrap().metadata(&absolute(operand));
+            match operator {
+                "-z" => Ok(operand.is_empty()),
+                "-n" => Ok(!operand.is_empty()),
+                "-e" => Ok(file().is_ok()),
+                "-f" => Ok(file().is_ok_and(|meta| !meta.is_dir)),
+                "-d" => Ok(file().is_ok_and(|meta| meta.is_dir)),
+                "-s" => Ok(file().is_ok_and(|meta| meta.size > 0)),
+                _ => Err(format!("{}: unary operator expected", operator)),
+            }
+        }
+        [_, operator, _] => Err(format!("{}: binary operator expected", operator)),
+        _ => Err("too many arguments".to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cmd::fresh_shell;
+    use crate::io::{Buffer, Stdin};
+    use std::{future::Future, pin::pin, task::{Context, Poll, Waker}};
+
+    // runs a future that never has to wait, as commands without network
+    // access don't
+    fn block_on<T>(future: impl Future<Output = T>) -> T {
+        match pin!(future).poll(&mut Context::from_waker(Waker::noop())) {
+            Poll::Ready(value) => value,
+            Poll::Pending => panic!("the future waited"),
+        }
+    }
+
+    #[test]
+    fn test_expressions() {
+        let _turn = fresh_shell();
+        assert_eq!(evaluate(&["abc"]), Ok(true));
+        assert_eq!(evaluate(&["-z", ""]), Ok(true));
+        assert_eq!(evaluate(&["!", "a", "=", "b"]), Ok(true));
+        assert_eq!(evaluate(&["!", "=", "!"]), Ok(true));
+        assert_eq!(evaluate(&["!", "-z", "x"]), Ok(true));
+        assert_eq!(evaluate(&["!", ""]), Ok(true));
+        assert_eq!(evaluate(&["a", "b", "c"]), Err("b: binary operator expected".to_string()));
+        assert_eq!(evaluate(&["10", "-gt", "9"]), Ok(true));
+        assert_eq!(evaluate(&["x", "-lt", "1"]), Err("x: integer expression expected".to_string()));
+        assert_eq!(evaluate(&["-d", "/tmp"]), Ok(true));
+        assert_eq!(evaluate(&["-q", "x"]), Err("-q: unary operator expected".to_string()));
+    }
+
+    #[test]
+    fn scripts_run_functions_loops_and_cases() {
+        let _turn = fresh_shell();
+        let stdout = Buffer::new();
+        let mut io = Io { stdin: Stdin::default(), stdout: Box::new(stdout.clone()), stderr: Box::new(stdout.clone()) };
+        let script = r#"#!/bin/sh
+            greet() {
+                local who=$1
+                if [ -z "$who" ]; then
+                    return 3
+                elif test "$who" = root; then
+                    echo "hello, admin"
+                else
+                    echo "hello, $who"
+                fi
+                echo unreachable? no
+            }
+            kind() {
+                case $1 in
+                    *.txt|*.md) echo text;;
+                    [0-9]*) echo number;;
+                    *) echo other
+                esac
+            }
+            greet ada
+            greet; echo "status $?"
+            for file in notes.md 42 x; do kind $file; done
+            while test $# -gt 0; do echo "arg $1"; shift; done
+            until true; do echo never; done
+            echo "who=$who $0" > /tmp/out
+        "#;
+        VFS.lock().unwrap().write("/tmp/demo.sh", script.as_bytes(), false).unwrap();
+        let status = block_on(run_file("/tmp/demo.sh", vec!["a".to_string(), "b c".to_string()], &mut io));
+        assert_eq!(status, EXIT_SUCCESS);
+        assert_eq!(stdout.take(), "hello, ada\nunreachable? no\nstatus 3\ntext\nnumber\nother\narg a\narg b c\n");
+        assert_eq!(VFS.lock().unwrap().read("/tmp/out").unwrap(), b"who= /tmp/demo.sh\n");
+        assert!(FRAMES.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn scripts_that_cant_run_are_reported() {
+        let _turn = fresh_shell();
+        let stdout = Buffer::new();
+        let mut io = Io { stdin: Stdin::default(), stdout: Box::new(stdout.clone()), stderr: Box::new(stdout.clone()) };
+        VFS.lock().unwrap().write("/tmp/bad.sh", b"#!/usr/bin/python\nprint(1)", false).unwrap();
+        assert_eq!(block_on(run_file("/tmp/bad.sh", Vec::new(), &mut io)), EXIT_NOT_EXECUTABLE);
+        assert_eq!(stdout.take(), "buudunn: /tmp/bad.sh: /usr/bin/python: bad interpreter\n");
+        VFS.lock().unwrap().write("/tmp/broken.sh", b"#!/bin/sh\necho ok\nif true; then\n", false).unwrap();
+        assert_eq!(block_on(run_file("/tmp/broken.sh", Vec::new(), &mut io)), EXIT_USAGE);
+        assert_eq!(stdout.take(), "/tmp/broken.sh: line 4: syntax error: unexpected end of file\n\n^\n");
+    }
+}
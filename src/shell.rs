@@ -0,0 +1,248 @@
+use crate::glob::has_wildcards;
+use crate::lexer::{Word, WordPart};
+use meval::tokenizer::{Operation, Token};
+
+// A word with its variables filled in, before wildcards are matched.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Expansion {
+    pub text: String,
+    // the text as a glob pattern, with everything quoted escaped, when it
+    // has unquoted wildcards
+    pub pattern: Option<String>,
+}
+
+// Fills in a word's variables using `lookup`, and its command
+// substitutions with `outputs`, what each of them printed in order. An
+// unquoted `~` at the start, alone or before a `/`, is `$HOME`. Unset
+// variables expand to nothing, and a word made only of unquoted expansions
+// that all came out empty is dropped (None), like bash does.
+pub fn expand_word(word: &Word, lookup: impl Fn(&str) -> Option<String>, outputs: &[String]) -> Result<Option<Expansion>, String> {
+    Ok(expand_fields(word, lookup, outputs, false)?.pop())
+}
+
+// Like `expand_word`, but what unquoted variables, substitutions and
+// arithmetic give is split into separate words at spaces, tabs and
+// newlines, as for the arguments of a command. Quoted parts stay whole.
+pub fn split_word(word: &Word, lookup: impl Fn(&str) -> Option<String>, outputs: &[String]) -> Result<Vec<Expansion>, String> {
+    expand_fields(word, lookup, outputs, true)
+}
+
+fn expand_fields(word: &Word, lookup: impl Fn(&str) -> Option<String>, outputs: &[String], split: bool) -> Result<Vec<Expansion>, String> {
+    let mut fields = Vec::new();
+    let mut text = String::new();
+    let mut pattern = String::new();
+    let mut keep = false;
+    let mut outputs = outputs.iter();
+    for (i, part) in word.parts.iter().enumerate() {
+        let (value, quoted) = match part {
+            WordPart::Literal(literal) if i == 0 && (literal == "~" || literal.starts_with("~/")) => match lookup("HOME") {
+                Some(home) => (format!("{}{}", home, &literal[1..]), false),
+                None => (literal.clone(), false),
+            },
+            WordPart::Literal(literal) => (literal.clone(), false),
+            WordPart::Quoted(literal) => (literal.clone(), true),
+            WordPart::Var { name, quoted } => (lookup(name).unwrap_or_default(), *quoted),
+            // trailing newlines are dropped, so `$(pwd)` fits inside a path
+            WordPart::Command { quoted, .. } => (outputs.next().map(|output| output.trim_end_matches('\n').to_string()).unwrap_or_default(), *quoted),
+            WordPart::Arithmetic { expr, quoted } => (arithmetic(expr, &lookup)?.to_string(), *quoted),
+        };
+        let splits = split && !quoted && !matches!(part, WordPart::Literal(_));
+        keep |= quoted || matches!(part, WordPart::Literal(_));
+        for ch in value.chars() {
+            if splits && matches!(ch, ' ' | '\t' | '\n') {
+                if keep || !text.is_empty() {
+                    fields.push(expansion(std::mem::take(&mut text), std::mem::take(&mut pattern)));
+                }
+                keep = false;
+                continue;
+            }
+            if quoted && matches!(ch, '\\' | '*' | '?' | '[' | ']') {
+                pattern.push('\\');
+            }
+            text.push(ch);
+            pattern.push(ch);
+        }
+    }
+    if keep || !text.is_empty() {
+        fields.push(expansion(text, pattern));
+    }
+    Ok(fields)
+}
+
+fn expansion(text: String, pattern: String) -> Expansion {
+    let pattern = has_wildcards(&pattern).then_some(pattern);
+    Expansion { text, pattern }
+}
+
+// The value of `$((expr))`. meval parses the expression, as it does for
+// `abacus eval`, but it is worked out in whole numbers the way shells do:
+// division rounds toward zero, `**` is a power, and variables can be named
+// with or without a `$`, counting as 0 when unset or empty.
+pub fn arithmetic(expr: &str, lookup: impl Fn(&str) -> Option<String>) -> Result<i64, String> {
+    let source = fill_vars(expr, &lookup).replace("**", "^");
+    if source.trim().is_empty() {
+        return Ok(0);
+    }
+    let error = |err: String| format!("{}: {}", expr.trim(), err);
+    let parsed: meval::Expr = source.parse().map_err(|err: meval::Error| error(err.to_string()))?;
+    let mut stack: Vec<i64> = Vec::new();
+    for token in parsed.iter() {
+        let value = match token {
+            Token::Number(number) if number.fract() == 0.0 => *number as i64,
+            Token::Number(number) => return Err(error(format!("{} isn't a whole number", number))),
+            Token::Var(name) => {
+                let value = lookup(name).unwrap_or_default();
+                match value.trim() {
+                    "" => 0,
+                    value => value.parse().map_err(|_| error(format!("{}={} isn't a whole number", name, value)))?,
+                }
+            }
+            Token::Unary(operation) => {
+                let value = stack.pop().unwrap_or_default();
+                match operation {
+                    Operation::Minus => value.wrapping_neg(),
+                    _ => value,
+                }
+            }
+            Token::Binary(operation) => {
+                let right = stack.pop().unwrap_or_default();
+                let left = stack.pop().unwrap_or_default();
+                match operation {
+                    Operation::Plus => left.wrapping_add(right),
+                    Operation::Minus => left.wrapping_sub(right),
+                    Operation::Times => left.wrapping_mul(right),
+                    Operation::Div | Operation::Rem if right == 0 => return Err(error("division by 0".to_string())),
+                    Operation::Div => left.wrapping_div(right),
+                    Operation::Rem => left.wrapping_rem(right),
+                    Operation::Pow if right < 0 => return Err(error("exponent less than 0".to_string())),
+                    Operation::Pow => left.wrapping_pow(u32::try_from(right).unwrap_or(u32::MAX)),
+                }
+            }
+            Token::Func(name, _) => return Err(error(format!("{}: no functions in arithmetic", name))),
+            _ => return Err(error("syntax error".to_string())),
+        };
+        stack.push(value);
+    }
+    Ok(stack.pop().unwrap_or_default())
+}
+
+// `expr` with `$NAME`, `${NAME}` and the special variables replaced by
+// their values
+fn fill_vars(expr: &str, lookup: impl Fn(&str) -> Option<String>) -> String {
+    let mut filled = String::new();
+    let mut rest = expr;
+    while let Some(start) = rest.find('$') {
+        filled.push_str(&rest[..start]);
+        let after = &rest[start + 1..];
+        let (name, len) = match after.chars().next() {
+            Some('{') => match after.find('}') {
+                Some(end) => (&after[1..end], end + 1),
+                None => ("", 0),
+            },
+            Some('?' | '#' | '@' | '*' | '0'..='9') => (&after[..1], 1),
+            _ => {
+                let len = after.find(|ch: char| !(ch.is_ascii_alphanumeric() || ch == '_')).unwrap_or(after.len());
+                (&after[..len], len)
+            }
+        };
+        match name.is_empty() {
+            true => filled.push('$'),
+            false => filled.push_str(&lookup(name).unwrap_or_default()),
+        }
+        rest = &after[len..];
+    }
+    filled.push_str(rest);
+    filled
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::{tokenize, Token};
+
+    fn word(source: &str) -> Word {
+        match tokenize(source).unwrap().remove(0) {
+            (Token::Word(word), _) => word,
+            _ => panic!("not a word"),
+        }
+    }
+
+    #[test]
+    fn variable_expansion() {
+        let lookup = |name: &str| match name {
+            "?" => Some("127".to_string()),
+            "USER" => Some("guest".to_string()),
+            "HOME" => Some("/home/guest".to_string()),
+            "Q" => Some("say \"hi\"".to_string()),
+            _ => None,
+        };
+        let expand = |source: &str| expand_word(&word(source), lookup, &[]).unwrap().map(|expansion| expansion.text);
+        assert_eq!(expand("$?"), Some("127".to_string()));
+        assert_eq!(expand("\"it's $USER\""), Some("it's guest".to_string()));
+        assert_eq!(expand("'$USER'\\$USER"), Some("$USER$USER".to_string()));
+        assert_eq!(expand("${USER}s"), Some("guests".to_string()));
+        assert_eq!(expand("\"$Q\""), Some("say \"hi\"".to_string()));
+        assert_eq!(expand("$NOPE"), None);
+        assert_eq!(expand("~/notes"), Some("/home/guest/notes".to_string()));
+        assert_eq!(expand("~"), Some("/home/guest".to_string()));
+        assert_eq!(expand("\"~\"/notes"), Some("~/notes".to_string()));
+        assert_eq!(expand("a~"), Some("a~".to_string()));
+        assert_eq!(expand("\"$NOPE\""), Some(String::new()));
+        let outputs = ["/home/guest\n\n".to_string(), String::new()];
+        let expand = |source: &str| expand_word(&word(source), lookup, &outputs).unwrap().map(|expansion| expansion.text);
+        assert_eq!(expand("$(pwd)/`echo`x"), Some("/home/guest/x".to_string()));
+        assert_eq!(expand("$(pwd)$((1 + 2))"), Some("/home/guest3".to_string()));
+        let empty = [String::new()];
+        assert_eq!(expand_word(&word("\"$(true)\""), lookup, &empty).unwrap().map(|expansion| expansion.text), Some(String::new()));
+        assert_eq!(expand_word(&word("$(true)"), lookup, &empty).unwrap(), None);
+    }
+
+    #[test]
+    fn unquoted_expansions_are_split() {
+        let lookup = |name: &str| match name {
+            "ls" => Some(" a.txt\tb c ".to_string()),
+            _ => None,
+        };
+        let outputs = ["one two\n".to_string()];
+        let split = |source: &str| split_word(&word(source), lookup, &outputs).unwrap().into_iter().map(|expansion| expansion.text).collect::<Vec<_>>();
+        assert_eq!(split("$ls"), vec!["a.txt", "b", "c"]);
+        assert_eq!(split("x$ls"), vec!["x", "a.txt", "b", "c"]);
+        assert_eq!(split("\"$ls\""), vec![" a.txt\tb c "]);
+        assert_eq!(split("$(ls)-\"$ls\""), vec!["one", "two- a.txt\tb c "]);
+        assert_eq!(split("'a b'$NOPE"), vec!["a b"]);
+        assert_eq!(split("$NOPE"), Vec::<String>::new());
+        assert_eq!(expand_word(&word("$ls"), lookup, &[]).unwrap().map(|expansion| expansion.text), Some(" a.txt\tb c ".to_string()));
+    }
+
+    #[test]
+    fn only_unquoted_wildcards_are_patterns() {
+        let pattern = |source: &str| expand_word(&word(source), |_| Some("*".to_string()), &[]).unwrap().unwrap().pattern;
+        assert_eq!(pattern("*.txt"), Some("*.txt".to_string()));
+        assert_eq!(pattern("'*'.txt"), None);
+        assert_eq!(pattern("\\*x"), None);
+        assert_eq!(pattern("\"[a]\"*"), Some("\\[a\\]*".to_string()));
+        assert_eq!(pattern("\"$X\""), None);
+        assert_eq!(pattern("plain"), None);
+    }
+
+    #[test]
+    fn arithmetic_uses_whole_numbers() {
+        let lookup = |name: &str| match name {
+            "n" => Some("7".to_string()),
+            "1" => Some("3".to_string()),
+            "word" => Some("seven".to_string()),
+            _ => None,
+        };
+        let eval = |expr: &str| arithmetic(expr, lookup);
+        assert_eq!(eval("1 + 2 * 3"), Ok(7));
+        assert_eq!(eval("n / 2"), Ok(3));
+        assert_eq!(eval("-$n % ${1}"), Ok(-1));
+        assert_eq!(eval("2 ** 10 - (n - 1)"), Ok(1018));
+        assert_eq!(eval("unset + 1"), Ok(1));
+        assert_eq!(eval(" "), Ok(0));
+        assert_eq!(eval("n / 0"), Err("n / 0: division by 0".to_string()));
+        assert_eq!(eval("1.5"), Err("1.5: 1.5 isn't a whole number".to_string()));
+        assert_eq!(eval("word"), Err("word: word=seven isn't a whole number".to_string()));
+        assert!(eval("1 +").is_err());
+    }
+}
@@ -0,0 +1,72 @@
+use std::collections::HashMap;
+
+// A string key-value store for anything that should survive a page reload.
+pub trait Storage: Send {
+    fn get(&self, key: &str) -> Option<String>;
+    fn set(&mut self, key: &str, value: &str);
+    fn remove(&mut self, key: &str);
+}
+
+// Keeps everything in memory. Used for native tests and whenever the browser
+// refuses access to localStorage.
+#[derive(Default)]
+pub struct MemoryStorage {
+    values: HashMap<String, String>,
+}
+
+impl Storage for MemoryStorage {
+    fn get(&self, key: &str) -> Option<String> {
+        self.values.get(key).cloned()
+    }
+
+    fn set(&mut self, key: &str, value: &str) {
+        self.values.insert(key.to_string(), value.to_string());
+    }
+
+    fn remove(&mut self, key: &str) {
+        self.values.remove(key);
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+pub struct LocalStorage;
+
+#[cfg(target_arch = "wasm32")]
+impl LocalStorage {
+    pub fn new() -> Option<LocalStorage> {
+        LocalStorage::storage().map(|_| LocalStorage)
+    }
+
+    // web_sys::Storage isn't Send, so it is looked up again on every access
+    fn storage() -> Option<web_sys::Storage> {
+        web_sys::window()?.local_storage().ok()?
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+impl Storage for LocalStorage {
+    fn get(&self, key: &str) -> Option<String> {
+        LocalStorage::storage()?.get_item(key).ok()?
+    }
+
+    fn set(&mut self, key: &str, value: &str) {
+        if let Some(storage) = LocalStorage::storage() {
+            let _ = storage.set_item(key, value);
+        }
+    }
+
+    fn remove(&mut self, key: &str) {
+        if let Some(storage) = LocalStorage::storage() {
+            let _ = storage.remove_item(key);
+        }
+    }
+}
+
+// localStorage in the browser, memory everywhere else
+pub fn default_storage() -> Box<dyn Storage> {
+    #[cfg(target_arch = "wasm32")]
+    if let Some(storage) = LocalStorage::new() {
+        return Box::new(storage);
+    }
+    Box::new(MemoryStorage::default())
+}
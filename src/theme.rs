@@ -0,0 +1,19 @@
+// Markup for the colours the shell itself draws with, so output and the
+// prompt don't each hardcode their own.
+pub struct Theme {
+    pub text: &'static str,
+    pub error: &'static str,
+    pub user: &'static str,
+    pub cwd: &'static str,
+    pub dir: &'static str,
+    pub prompt: &'static str,
+}
+
+pub const THEME: Theme = Theme {
+    text: "\\#FFFFFF",
+    error: "\\#FFC0C0",
+    user: "\\#90EE90",
+    cwd: "\\#ADD8E6",
+    dir: "\\#87CEFA",
+    prompt: "\\#FFFF00",
+};
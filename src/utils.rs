@@ -1,7 +1,11 @@
 use lazy_static::lazy_static;
 use std::sync::Mutex;
-use wasm_bindgen::JsValue;
 use wasm_bindgen::prelude::*;
+use crate::screen::Screen;
+use crate::editor::{LineEditor, ReverseSearch};
+use crate::history::HISTORY;
+use crate::cmd::complete_cmd;
+use crate::render::{grid_size, line_height, render};
 
 #[wasm_bindgen]
 extern "C" {
@@ -21,6 +25,7 @@ extern "C" {
     fn log_many(a: &str, b: &str);
 }
 
+#[allow(unused_macros)]
 macro_rules! console_log {
     // Note that this is using the `log` function imported above during
     // `bare_bones`
@@ -28,36 +33,56 @@ macro_rules! console_log {
 }
 
 lazy_static! {
-    static ref CURSOR_POS: Mutex<(f64, f64)> = Mutex::new((10.0, 20.0));
-    static ref CURSOR_LOCK_POS: Mutex<(f64, f64)> = Mutex::new((0.0, 0.0));
-    static ref IS_CURSOR_LOCKED: Mutex<bool> = Mutex::new(false);
+    static ref SCREEN: Mutex<Screen> = Mutex::new(Screen::new(80, 24));
     static ref IS_INPUT_LOCKED: Mutex<bool> = Mutex::new(true);
-    static ref MAX_POS: Mutex<(f64, f64)> = Mutex::new((0.0, 0.0));
     static ref FONT_SIZE: Mutex<f64> = Mutex::new(14.0);
-    static ref CMD_BANK: Mutex<String> = Mutex::new(String::new());
+    static ref CMD_BANK: Mutex<LineEditor> = Mutex::new(LineEditor::new());
+    static ref PROMPT: Mutex<String> = Mutex::new(String::new());
+    static ref SEARCH: Mutex<Option<ReverseSearch>> = Mutex::new(None);
 }
 
 pub fn set_canvas_size(canvas: &web_sys::HtmlCanvasElement, window: &web_sys::Window) {
     canvas.set_width(window.inner_width().unwrap().as_f64().unwrap() as u32);
-    canvas.set_height(window.inner_height().unwrap().as_f64().unwrap() as u32 * 10);
-    let max_x = (canvas.width() as f64) - 20.0;
-    let max_y = (canvas.height() as f64) - 20.0;
-    *MAX_POS.lock().unwrap() = (max_x, max_y);
+    canvas.set_height(window.inner_height().unwrap().as_f64().unwrap() as u32);
+    let font_size = *FONT_SIZE.lock().unwrap();
+    let (cols, rows) = grid_size(canvas.width() as f64, canvas.height() as f64, font_size);
+    SCREEN.lock().unwrap().resize(cols, rows);
 }
 
-pub fn lock_cursor(position: (f64, f64)) {
-    *MAX_POS.lock().unwrap() = position;
-    *IS_CURSOR_LOCKED.lock().unwrap() = true;
+// Fits the canvas to the window again and re-wraps the screen to match.
+#[wasm_bindgen]
+pub fn resizehandler(context: &web_sys::CanvasRenderingContext2d) {
+    let window = web_sys::window().unwrap();
+    if let Some(canvas) = context.canvas() {
+        set_canvas_size(&canvas, &window);
+        let mut screen = SCREEN.lock().unwrap();
+        render(&mut screen, context, *FONT_SIZE.lock().unwrap());
+    }
 }
 
-pub fn lock_cursor_here() {
-    let cursor_pos = *CURSOR_POS.lock().unwrap();
-    *CURSOR_LOCK_POS.lock().unwrap() = cursor_pos;
-    *IS_CURSOR_LOCKED.lock().unwrap() = true;
+#[wasm_bindgen]
+pub fn set_scrollback(lines: usize) {
+    SCREEN.lock().unwrap().set_scrollback(lines);
+}
+
+// positive amounts scroll back through older output
+pub fn scroll(lines: isize, context: &web_sys::CanvasRenderingContext2d) {
+    let mut screen = SCREEN.lock().unwrap();
+    screen.scroll(lines);
+    render(&mut screen, context, *FONT_SIZE.lock().unwrap());
 }
 
-pub fn unlock_cursor() {
-    *IS_CURSOR_LOCKED.lock().unwrap() = false;
+pub fn scroll_page(pages: isize, context: &web_sys::CanvasRenderingContext2d) {
+    let page = SCREEN.lock().unwrap().rows().saturating_sub(1).max(1) as isize;
+    scroll(pages * page, context);
+}
+
+pub fn get_line_height() -> f64 {
+    line_height(*FONT_SIZE.lock().unwrap())
+}
+
+pub fn lock_cursor_here() {
+    SCREEN.lock().unwrap().lock_here();
 }
 
 pub fn lock_input() {
@@ -73,86 +98,186 @@ pub fn get_is_input_locked() -> bool {
 }
 
 pub fn draw_text(text: &str, context: &web_sys::CanvasRenderingContext2d) {
-    let mut cursor_pos = *CURSOR_POS.lock().unwrap();
-        let max_x = MAX_POS.lock().unwrap().0;
-        let font_size = *FONT_SIZE.lock().unwrap();
-        let mut hex_index = 0;
-
-        if text == "\n" {
-            cursor_pos.0 = 10.0;
-            cursor_pos.1 += font_size + 4.0;
-        } else {
-            for i in 0..text.chars().count() {
-                if hex_index > 0 {
-                    hex_index -= 1;
-                    continue;
-                }
-                let ch = text.chars().nth(i).expect(&format!("ERROR: cant get char of '{}' at index {}", text, i));
-                if ch == '\\' {
-                    let next_hex_chars = text.chars().skip(i + 1).take(7).collect::<String>();
-                    if next_hex_chars.starts_with("#") {
-                        context.set_stroke_style(&JsValue::from_str(&next_hex_chars));
-                        context.set_fill_style(&JsValue::from_str(&next_hex_chars));
-                        hex_index += 7;
-                        continue;
-                    }
-                }
-                if ch == '\n' {
-                    cursor_pos.0 = 10.0;
-                    cursor_pos.1 += font_size + 4.0;
-                    continue;
-                } else {
-                    if cursor_pos.0 + font_size / 2.0 >= max_x {
-                        // Check if the cursor is about to go off screen or beyond the max_x
-                        cursor_pos.0 = 10.0; // Reset the cursor x
-                        cursor_pos.1 += font_size + 4.0; // Increase the cursor y
-                    }
-                    context.fill_text(&ch.to_string(), cursor_pos.0, cursor_pos.1).unwrap(); // Draw the character
-                    cursor_pos.0 += font_size / 2.0; // Increment the cursor x
-                }
-            }
+    let mut screen = SCREEN.lock().unwrap();
+    screen.write(text);
+    screen.reset_style();
+    if let Some(title) = screen.take_title() {
+        if let Some(document) = web_sys::window().and_then(|window| window.document()) {
+            document.set_title(&title);
         }
-        context.set_stroke_style(&JsValue::from_str("#FFFFFF"));
-        context.set_fill_style(&JsValue::from_str("#FFFFFF"));
+    }
+    render(&mut screen, context, *FONT_SIZE.lock().unwrap());
+}
+
+// Starts a new line unless the cursor is already at the start of one.
+pub fn start_new_line(context: &web_sys::CanvasRenderingContext2d) {
+    let at_line_start = SCREEN.lock().unwrap().cursor().1 == 0;
+    if !at_line_start {
+        draw_text("\n", context);
+    }
+}
 
-        *CURSOR_POS.lock().unwrap() = (cursor_pos.0, cursor_pos.1); // Update the global cursor x and y value
+// Draws the prompt and locks the cursor after it, so input starts there.
+pub fn draw_prompt(prompt: &str, context: &web_sys::CanvasRenderingContext2d) {
+    SCREEN.lock().unwrap().mark_prompt();
+    draw_text(prompt, context);
+    lock_cursor_here();
+    *PROMPT.lock().unwrap() = prompt.to_string();
 }
 
-pub fn backspace(context: &web_sys::CanvasRenderingContext2d) {
-    let mut cursor_pos = *CURSOR_POS.lock().unwrap();
-    let max_x = MAX_POS.lock().unwrap().0;
-    let lock_pos = CURSOR_LOCK_POS.lock().unwrap();
-    let font_size = *FONT_SIZE.lock().unwrap();
+// Puts the prompt and the command being typed back after something else,
+// like the history search, was drawn over them.
+fn restore_prompt(context: &web_sys::CanvasRenderingContext2d) {
+    {
+        let mut screen = SCREEN.lock().unwrap();
+        if let Some(start) = screen.prompt_start() {
+            screen.clear_from(start);
+            screen.set_cursor(start);
+        }
+    }
+    let prompt = PROMPT.lock().unwrap().clone();
+    draw_prompt(&prompt, context);
+    edit_cmd_bank(context, |_| true);
+}
+
+fn draw_over_prompt(text: &str, context: &web_sys::CanvasRenderingContext2d) {
+    let mut screen = SCREEN.lock().unwrap();
+    if let Some(start) = screen.prompt_start() {
+        screen.clear_from(start);
+        screen.set_cursor(start);
+        screen.write_plain(text);
+    }
+    render(&mut screen, context, *FONT_SIZE.lock().unwrap());
+}
 
-    if
-        (cursor_pos.0 - font_size / 2.0 < 9.9 &&
-        cursor_pos.1 - font_size + 4.0 < 20.0) || (cursor_pos.0 - font_size / 2.0 < lock_pos.0 &&
-        cursor_pos.1 - font_size + 4.0 < lock_pos.1)
-    {/* can't backspace */} else {
-        if cursor_pos.0 - font_size / 2.0 < 9.0 {
-            cursor_pos.0 = max_x - 9.0; // Move cursor to the end of the previous line
-            cursor_pos.1 -= font_size + 4.0; // Move cursor up to the previous line
+// Writes text on the lines below the command being typed, then draws the
+// prompt and the command again underneath.
+fn print_below_input(text: &str, context: &web_sys::CanvasRenderingContext2d) {
+    {
+        let cmd_bank = CMD_BANK.lock().unwrap();
+        let mut screen = SCREEN.lock().unwrap();
+        if let Some(start) = screen.lock() {
+            let end = screen.advance(start, &cmd_bank.text());
+            screen.set_cursor(end);
+        }
+        screen.write("\n");
+        screen.write_plain(text);
+        screen.write("\n");
+    }
+    let prompt = PROMPT.lock().unwrap().clone();
+    draw_prompt(&prompt, context);
+    edit_cmd_bank(context, |_| true);
+}
+
+// Tab: completes the word before the cursor as far as all the candidates
+// agree, and lists them when that doesn't get any further.
+pub fn complete_cmd_bank(context: &web_sys::CanvasRenderingContext2d) {
+    let before = CMD_BANK.lock().unwrap().text_before_cursor();
+    let word_start = before.rfind(char::is_whitespace).map(|i| i + 1).unwrap_or(0);
+    let word = &before[word_start..];
+    let candidates = complete_cmd(&before);
+
+    if candidates.len() == 1 {
+        // a completed directory is usually followed by more of the path
+        let space = if candidates[0].ends_with('/') { "" } else { " " };
+        let completion = format!("{}{}", candidates[0], space);
+        edit_cmd_bank(context, |cmd_bank| cmd_bank.replace_before_cursor(word.chars().count(), &completion));
+    } else if candidates.len() > 1 {
+        let prefix = common_prefix(&candidates);
+        if prefix.len() > word.len() {
+            edit_cmd_bank(context, |cmd_bank| cmd_bank.replace_before_cursor(word.chars().count(), &prefix));
         } else {
-            cursor_pos.0 -= font_size / 2.0; // Move cursor left by 8 pixels
+            print_below_input(&candidates.join("  "), context);
         }
-        context.set_stroke_style(&JsValue::from_str("#000000"));
-        context.set_fill_style(&JsValue::from_str("#000000"));
-        context.fill_rect(cursor_pos.0, cursor_pos.1 - font_size, font_size / 2.0, font_size + 4.0);
-        context.set_stroke_style(&JsValue::from_str("#FFFFFF"));
-        context.set_fill_style(&JsValue::from_str("#FFFFFF"));
-        *CURSOR_POS.lock().unwrap() = (cursor_pos.0, cursor_pos.1); // Update the global cursor x and y value
     }
 }
 
-// cmd bank things
-pub fn add_to_cmd_bank(txt: &str) {
-    let mut cmd_bank = CMD_BANK.lock().unwrap();
-    *cmd_bank += txt;
+fn common_prefix(words: &[String]) -> String {
+    let Some(first) = words.first() else { return String::new() };
+    let mut prefix = first.as_str();
+    for word in words {
+        while !word.starts_with(prefix) {
+            let mut chars = prefix.chars();
+            chars.next_back();
+            prefix = chars.as_str();
+        }
+    }
+    prefix.to_string()
+}
+
+// history search things
+pub fn is_searching_history() -> bool {
+    SEARCH.lock().unwrap().is_some()
+}
+
+pub fn start_history_search(context: &web_sys::CanvasRenderingContext2d) {
+    let search = ReverseSearch::new(&get_cmd_bank());
+    let line = search.prompt(HISTORY.lock().unwrap().entries());
+    *SEARCH.lock().unwrap() = Some(search);
+    draw_over_prompt(&line, context);
+}
+
+// Handles a key pressed during a history search. Returns true when the key
+// ended the search by accepting the match and should still be handled as a
+// normal key, like Enter running the accepted command.
+pub fn history_search_key(key: &str, ctrl: bool, context: &web_sys::CanvasRenderingContext2d) -> bool {
+    let mut search_slot = SEARCH.lock().unwrap();
+    let Some(search) = search_slot.as_mut() else { return true };
+    let history = HISTORY.lock().unwrap();
+    let entries = history.entries();
+
+    // the command line to go back to, and whether to handle the key after
+    let mut ended = None;
+    match key {
+        "Shift" | "Control" | "Alt" | "Meta" | "CapsLock" => {},
+        "Escape" => ended = Some((search.original.clone(), false)),
+        "g" | "G" if ctrl => ended = Some((search.original.clone(), false)),
+        "r" | "R" if ctrl => search.older(entries),
+        "Backspace" => search.pop(entries),
+        _ if key.chars().count() == 1 && !ctrl => search.push(key, entries),
+        _ => {
+            let accepted = search.found.and_then(|i| entries.get(i)).unwrap_or(&search.original);
+            ended = Some((accepted.clone(), true));
+        },
+    }
+    let line = search.prompt(entries);
+    if ended.is_some() {
+        *search_slot = None;
+    }
+    drop(history);
+    drop(search_slot);
+
+    match ended {
+        Some((text, handle_key)) => {
+            CMD_BANK.lock().unwrap().set_text(&text);
+            restore_prompt(context);
+            handle_key
+        },
+        None => {
+            draw_over_prompt(&line, context);
+            false
+        },
+    }
 }
 
-pub fn remove_last_from_cmd_bank() {
+// cmd bank things
+// Applies an edit to the command being typed and, if it changed anything,
+// redraws it after the prompt with the cursor at the editing position.
+pub fn edit_cmd_bank(context: &web_sys::CanvasRenderingContext2d, edit: impl FnOnce(&mut LineEditor) -> bool) {
     let mut cmd_bank = CMD_BANK.lock().unwrap();
-    cmd_bank.pop();
+    if !edit(&mut cmd_bank) {
+        return;
+    }
+    let mut screen = SCREEN.lock().unwrap();
+    if let Some(start) = screen.lock() {
+        screen.clear_from(start);
+        screen.write_plain(&cmd_bank.text());
+        // writing may have pushed old lines out of the scrollback
+        let start = screen.lock().unwrap_or(start);
+        let cursor = screen.advance(start, &cmd_bank.text_before_cursor());
+        screen.set_cursor(cursor);
+    }
+    render(&mut screen, context, *FONT_SIZE.lock().unwrap());
 }
 
 pub fn clear_cmd_bank() {
@@ -162,5 +287,5 @@ pub fn clear_cmd_bank() {
 
 pub fn get_cmd_bank() -> String {
     let cmd_bank = CMD_BANK.lock().unwrap();
-    cmd_bank.to_owned()
-}
\ No newline at end of file
+    cmd_bank.text()
+}
@@ -0,0 +1,499 @@
+use crate::env::ENV;
+use crate::memfs::MemoryFs;
+use crate::storage::{default_storage, MemoryStorage, Storage};
+use once_cell::sync::Lazy;
+use std::{fmt, sync::Mutex};
+
+pub static VFS: Lazy<Mutex<Vfs>> = Lazy::new(|| Mutex::new(Vfs::new("guest")));
+
+// puts back the tree a session starts with, before anything saved is loaded
+#[cfg(test)]
+pub fn reset() {
+    *VFS.lock().unwrap() = Vfs::new("guest");
+}
+
+// where the /home filesystem is saved
+const HOME_KEY: &str = "buudunn.home";
+
+// Mounts the /home saved in the browser in place of the empty one. Returns
+// a warning to show if the saved files couldn't be read.
+pub fn load_vfs() -> Option<String> {
+    let owner = ENV.lock().unwrap().get("USER").unwrap_or("guest").to_string();
+    let (vfs, warning) = Vfs::open(default_storage(), &owner);
+    *VFS.lock().unwrap() = vfs;
+    warning
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum VfsError {
+    NotFound,
+    NotADirectory,
+    IsADirectory,
+    AlreadyExists,
+    NotEmpty,
+    // e.g. moving a directory into itself, or removing /
+    InvalidArgument,
+    ReadOnly,
+    // e.g. removing a mount point
+    Busy,
+}
+
+impl fmt::Display for VfsError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            VfsError::NotFound => "No such file or directory",
+            VfsError::NotADirectory => "Not a directory",
+            VfsError::IsADirectory => "Is a directory",
+            VfsError::AlreadyExists => "File exists",
+            VfsError::NotEmpty => "Directory not empty",
+            VfsError::InvalidArgument => "Invalid argument",
+            VfsError::ReadOnly => "Read-only file system",
+            VfsError::Busy => "Device or resource busy",
+        })
+    }
+}
+
+pub type VfsResult<T> = Result<T, VfsError>;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Metadata {
+    pub is_dir: bool,
+    pub size: usize,
+    // milliseconds since the Unix epoch
+    pub mtime: f64,
+    pub mode: u16,
+    pub owner: String,
+}
+
+impl Metadata {
+    // "drwxr-xr-x" and the like
+    pub fn mode_string(&self) -> String {
+        let mut text = String::from(if self.is_dir { "d" } else { "-" });
+        for shift in [6, 3, 0] {
+            let bits = self.mode >> shift;
+            text.push(if bits & 4 != 0 { 'r' } else { '-' });
+            text.push(if bits & 2 != 0 { 'w' } else { '-' });
+            text.push(if bits & 1 != 0 { 'x' } else { '-' });
+        }
+        text
+    }
+}
+
+// What every mounted filesystem provides. Paths are absolute within the
+// filesystem, so a filesystem mounted at /tmp sees /tmp/a as /a.
+pub trait FileSystem: Send {
+    fn metadata(&self, path: &str) -> VfsResult<Metadata>;
+    // the names and metadata of a directory's entries, sorted by name
+    fn read_dir(&self, path: &str) -> VfsResult<Vec<(String, Metadata)>>;
+    fn read(&self, path: &str) -> VfsResult<Vec<u8>>;
+    // Replaces the file's contents, or adds to them when `append` is set.
+    // The file is created if it doesn't exist.
+    fn write(&mut self, path: &str, contents: &[u8], append: bool) -> VfsResult<()>;
+    // creates an empty file, or updates the modification time of what's there
+    fn touch(&mut self, path: &str) -> VfsResult<()>;
+    fn create_dir(&mut self, path: &str) -> VfsResult<()>;
+    // removes an empty directory
+    fn remove_dir(&mut self, path: &str) -> VfsResult<()>;
+    // removes a file, or a whole directory tree when `recursive` is set
+    fn remove(&mut self, path: &str, recursive: bool) -> VfsResult<()>;
+    // copies a file, or a whole directory tree when `recursive` is set
+    fn copy(&mut self, from: &str, to: &str, recursive: bool) -> VfsResult<()>;
+    fn rename(&mut self, from: &str, to: &str) -> VfsResult<()>;
+
+    // saves anything that should outlive the page
+    fn sync(&mut self) {}
+
+    fn read_only(&self) -> bool {
+        false
+    }
+
+    fn is_dir(&self, path: &str) -> bool {
+        matches!(self.metadata(path), Ok(meta) if meta.is_dir)
+    }
+
+    // creates the directory and any missing parents, like `mkdir -p`
+    fn create_dir_all(&mut self, path: &str) -> VfsResult<()> {
+        let mut current = String::new();
+        for part in components(path) {
+            current = format!("{}/{}", current, part);
+            match self.create_dir(&current) {
+                Err(VfsError::AlreadyExists) if self.is_dir(&current) => {}
+                Err(VfsError::AlreadyExists) => return Err(VfsError::NotADirectory),
+                result => result?,
+            }
+        }
+        Ok(())
+    }
+}
+
+pub struct Mount {
+    // where it is mounted
+    pub point: String,
+    // what was mounted, e.g. the archive or URL
+    pub source: String,
+    // the kind of filesystem, e.g. tmpfs
+    pub kind: String,
+    fs: Box<dyn FileSystem>,
+}
+
+impl Mount {
+    pub fn read_only(&self) -> bool {
+        self.fs.read_only()
+    }
+}
+
+// A copy of a file or directory tree, for copying between filesystems.
+enum Tree {
+    File(Vec<u8>),
+    Dir(Vec<(String, Tree)>),
+}
+
+// The session's filesystem: the filesystems in the mount table, each seen
+// at its mount point. Paths are absolute and normalized, see `normalize`.
+pub struct Vfs {
+    mounts: Vec<Mount>,
+}
+
+impl Vfs {
+    pub fn new(owner: &str) -> Vfs {
+        Vfs::open(Box::new(MemoryStorage::default()), owner).0
+    }
+
+    // The usual layout: a root filesystem with /tmp as a tmpfs and /home
+    // kept in `storage`, with the warning from reading /home if there is one.
+    pub fn open(storage: Box<dyn Storage>, owner: &str) -> (Vfs, Option<String>) {
+        let mut vfs = Vfs { mounts: vec![Mount { point: "/".to_string(), source: "rootfs".to_string(), kind: "tmpfs".to_string(), fs: Box::new(MemoryFs::new(owner)) }] };
+        for dir in ["/bin", "/home", "/mnt/remote", "/tmp"] {
+            let _ = vfs.create_dir_all(dir);
+        }
+        let _ = vfs.mount("/tmp", "tmpfs", "tmpfs", Box::new(MemoryFs::new(owner)));
+        let (home, warning) = MemoryFs::open(storage, HOME_KEY, owner);
+        let _ = vfs.mount("/home", "storage", "storage", Box::new(home));
+        let _ = vfs.create_dir(&format!("/home/{}", owner));
+        (vfs, warning)
+    }
+
+    pub fn mounts(&self) -> impl Iterator<Item = &Mount> {
+        self.mounts.iter()
+    }
+
+    // Mounts `fs` over the directory at `point`, hiding what was there.
+    pub fn mount(&mut self, point: &str, source: &str, kind: &str, fs: Box<dyn FileSystem>) -> VfsResult<()> {
+        if !self.metadata(point)?.is_dir {
+            return Err(VfsError::NotADirectory);
+        }
+        if self.mounts.iter().any(|mount| mount.point == point) {
+            return Err(VfsError::Busy);
+        }
+        self.mounts.push(Mount { point: point.to_string(), source: source.to_string(), kind: kind.to_string(), fs });
+        Ok(())
+    }
+
+    // Unmounts what is mounted at `point`, which can't be / or have other
+    // filesystems mounted inside it.
+    pub fn unmount(&mut self, point: &str) -> VfsResult<()> {
+        let index = self.mounts.iter().position(|mount| mount.point == point).ok_or(VfsError::InvalidArgument)?;
+        if point == "/" || self.mounts.iter().any(|mount| mount.point != point && is_within(&mount.point, point)) {
+            return Err(VfsError::Busy);
+        }
+        self.mounts.remove(index).fs.sync();
+        Ok(())
+    }
+
+    // the mount holding `path`, and the path within it
+    fn resolve<'a>(&self, path: &'a str) -> (usize, &'a str) {
+        let (index, mount) = self.mounts.iter().enumerate()
+            .filter(|(_, mount)| is_within(path, &mount.point))
+            .max_by_key(|(_, mount)| mount.point.len())
+            .expect("/ is always mounted");
+        match &path[mount.point.trim_end_matches('/').len()..] {
+            "" => (index, "/"),
+            inner => (index, inner),
+        }
+    }
+
+    fn fs(&self, path: &str) -> (&dyn FileSystem, String) {
+        let (index, inner) = self.resolve(path);
+        (self.mounts[index].fs.as_ref(), inner.to_string())
+    }
+
+    fn fs_mut(&mut self, path: &str) -> (&mut dyn FileSystem, String) {
+        let (index, inner) = self.resolve(path);
+        (self.mounts[index].fs.as_mut(), inner.to_string())
+    }
+
+    // whether `path` is a mount point or has one inside it
+    fn busy(&self, path: &str) -> bool {
+        self.mounts.iter().any(|mount| mount.point != "/" && is_within(&mount.point, path))
+    }
+
+    fn tree(&self, path: &str) -> VfsResult<Tree> {
+        match self.metadata(path)?.is_dir {
+            true => self.read_dir(path)?.into_iter()
+                .map(|(name, _)| Ok((name.clone(), self.tree(&format!("{}/{}", path.trim_end_matches('/'), name))?)))
+                .collect::<VfsResult<_>>()
+                .map(Tree::Dir),
+            false => self.read(path).map(Tree::File),
+        }
+    }
+
+    // puts a copied tree at `path`, which may only replace a file with a file
+    fn place(&mut self, path: &str, tree: Tree) -> VfsResult<()> {
+        match (self.metadata(path), tree) {
+            (Ok(meta), _) if meta.is_dir => Err(VfsError::IsADirectory),
+            (_, Tree::File(data)) => self.write(path, &data, false),
+            (Ok(_), Tree::Dir(_)) => Err(VfsError::NotADirectory),
+            (Err(_), Tree::Dir(entries)) => {
+                self.create_dir(path)?;
+                entries.into_iter().try_for_each(|(name, tree)| self.place(&format!("{}/{}", path.trim_end_matches('/'), name), tree))
+            }
+        }
+    }
+}
+
+impl FileSystem for Vfs {
+    fn metadata(&self, path: &str) -> VfsResult<Metadata> {
+        let (fs, inner) = self.fs(path);
+        fs.metadata(&inner)
+    }
+
+    // mount points are listed with the metadata of what is mounted there
+    fn read_dir(&self, path: &str) -> VfsResult<Vec<(String, Metadata)>> {
+        let (fs, inner) = self.fs(path);
+        let mut entries = fs.read_dir(&inner)?;
+        for (name, meta) in entries.iter_mut() {
+            let full = format!("{}/{}", path.trim_end_matches('/'), name);
+            if let Some(mount) = self.mounts.iter().find(|mount| mount.point == full) {
+                *meta = mount.fs.metadata("/")?;
+            }
+        }
+        Ok(entries)
+    }
+
+    fn read(&self, path: &str) -> VfsResult<Vec<u8>> {
+        let (fs, inner) = self.fs(path);
+        fs.read(&inner)
+    }
+
+    fn write(&mut self, path: &str, contents: &[u8], append: bool) -> VfsResult<()> {
+        let (fs, inner) = self.fs_mut(path);
+        fs.write(&inner, contents, append)
+    }
+
+    fn touch(&mut self, path: &str) -> VfsResult<()> {
+        let (fs, inner) = self.fs_mut(path);
+        fs.touch(&inner)
+    }
+
+    fn create_dir(&mut self, path: &str) -> VfsResult<()> {
+        let (fs, inner) = self.fs_mut(path);
+        fs.create_dir(&inner)
+    }
+
+    fn remove_dir(&mut self, path: &str) -> VfsResult<()> {
+        if self.busy(path) {
+            return Err(VfsError::Busy);
+        }
+        let (fs, inner) = self.fs_mut(path);
+        fs.remove_dir(&inner)
+    }
+
+    fn remove(&mut self, path: &str, recursive: bool) -> VfsResult<()> {
+        if self.busy(path) {
+            return Err(VfsError::Busy);
+        }
+        let (fs, inner) = self.fs_mut(path);
+        fs.remove(&inner, recursive)
+    }
+
+    // copies between filesystems file by file
+    fn copy(&mut self, from: &str, to: &str, recursive: bool) -> VfsResult<()> {
+        let ((from_mount, from_inner), (to_mount, to_inner)) = (self.resolve(from), self.resolve(to));
+        if from_mount == to_mount {
+            return self.mounts[from_mount].fs.copy(from_inner, to_inner, recursive);
+        }
+        if self.is_dir(from) {
+            if !recursive {
+                return Err(VfsError::IsADirectory);
+            }
+            if is_within(to, from) {
+                return Err(VfsError::InvalidArgument);
+            }
+        }
+        let tree = self.tree(from)?;
+        self.place(to, tree)
+    }
+
+    // moves between filesystems by copying, then removing the original
+    fn rename(&mut self, from: &str, to: &str) -> VfsResult<()> {
+        if self.busy(from) {
+            return Err(VfsError::Busy);
+        }
+        let ((from_mount, from_inner), (to_mount, to_inner)) = (self.resolve(from), self.resolve(to));
+        if from_mount == to_mount {
+            return self.mounts[from_mount].fs.rename(from_inner, to_inner);
+        }
+        if self.mounts[from_mount].fs.read_only() {
+            return Err(VfsError::ReadOnly);
+        }
+        self.copy(from, to, true)?;
+        self.remove(from, true)
+    }
+
+    fn sync(&mut self) {
+        for mount in self.mounts.iter_mut() {
+            mount.fs.sync();
+        }
+    }
+}
+
+pub fn components(path: &str) -> impl Iterator<Item = &str> {
+    path.split('/').filter(|part| !part.is_empty())
+}
+
+// "/a/b" -> ("/a", "b"); None for "/"
+pub fn split_parent(path: &str) -> Option<(&str, &str)> {
+    let path = path.trim_end_matches('/');
+    let (parent, name) = path.rsplit_once('/')?;
+    Some((if parent.is_empty() { "/" } else { parent }, name))
+}
+
+// whether `path` is `dir` or somewhere inside it
+pub fn is_within(path: &str, dir: &str) -> bool {
+    path == dir || path.starts_with(&format!("{}/", dir.trim_end_matches('/')))
+}
+
+// Makes `path` absolute against `cwd`, expanding a leading `~` to `home` and
+// resolving `.` and `..`.
+pub fn normalize(cwd: &str, home: &str, path: &str) -> String {
+    let path = match path.strip_prefix('~') {
+        Some(rest) if rest.is_empty() || rest.starts_with('/') => format!("{}{}", home, rest),
+        _ => path.to_string(),
+    };
+    let start = if path.starts_with('/') { "" } else { cwd };
+    let mut parts: Vec<&str> = Vec::new();
+    for part in components(start).chain(components(&path)) {
+        match part {
+            "." => {}
+            ".." => {
+                parts.pop();
+            }
+            part => parts.push(part),
+        }
+    }
+    format!("/{}", parts.join("/"))
+}
+
+// `path` made absolute against the session's PWD and HOME
+pub fn absolute(path: &str) -> String {
+    let env = ENV.lock().unwrap();
+    normalize(env.get("PWD").unwrap_or("/"), env.get("HOME").unwrap_or("/"), path)
+}
+
+// Completions for a path being typed: the entries of the directory it names
+// so far, with that directory part kept and a `/` after subdirectories.
+pub fn complete_path(word: &str) -> Vec<String> {
+    let dir = word.rfind('/').map(|i| &word[..=i]).unwrap_or("");
+    let path = absolute(if dir.is_empty() { "." } else { dir });
+    let entries = VFS.lock().unwrap().read_dir(&path).unwrap_or_default();
+    entries.into_iter()
+        .map(|(name, meta)| format!("{}{}{}", dir, name, if meta.is_dir { "/" } else { "" }))
+        .collect()
+}
+
+#[cfg(target_arch = "wasm32")]
+pub fn now() -> f64 {
+    js_sys::Date::now()
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+pub fn now() -> f64 {
+    std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).map(|time| time.as_millis() as f64).unwrap_or_default()
+}
+
+// "Oct 18 14:05" in UTC, for `ls -l`
+pub fn format_time(mtime: f64) -> String {
+    const MONTHS: [&str; 12] = ["Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec"];
+    let seconds = (mtime / 1000.0) as i64;
+    let (days, time) = (seconds.div_euclid(86400), seconds.rem_euclid(86400));
+    // civil_from_days, from Howard Hinnant's date algorithms
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    format!("{} {:>2} {:02}:{:02}", MONTHS[(month - 1) as usize], day, time / 3600, time % 3600 / 60)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn paths_are_normalized() {
+        assert_eq!(normalize("/home/guest", "/home/guest", "docs/../notes.txt"), "/home/guest/notes.txt");
+        assert_eq!(normalize("/tmp", "/home/guest", "~/a/./b"), "/home/guest/a/b");
+        assert_eq!(normalize("/tmp", "/home/guest", "/../.."), "/");
+        assert_eq!(normalize("/tmp", "/home/guest", "~x"), "/tmp/~x");
+        assert_eq!(split_parent("/a/b/"), Some(("/a", "b")));
+        assert_eq!(split_parent("/a"), Some(("/", "a")));
+        assert_eq!(split_parent("/"), None);
+    }
+
+    #[test]
+    fn paths_are_routed_to_their_mounts() {
+        let mut vfs = Vfs::new("guest");
+        vfs.write("/tmp/a", b"x", false).unwrap();
+        vfs.create_dir("/tmp/sub").unwrap();
+        assert_eq!(vfs.resolve("/tmp/sub"), (1, "/sub"));
+        assert_eq!(vfs.resolve("/tmp"), (1, "/"));
+        assert_eq!(vfs.resolve("/tmpfile"), (0, "/tmpfile"));
+        assert_eq!(vfs.resolve("/home/guest"), (2, "/guest"));
+
+        vfs.mount("/tmp/sub", "tmpfs", "tmpfs", Box::new(MemoryFs::new("guest"))).unwrap();
+        vfs.write("/tmp/sub/b", b"y", false).unwrap();
+        assert_eq!(vfs.read("/tmp/sub/b").unwrap(), b"y");
+        assert_eq!(vfs.mount("/tmp/sub", "tmpfs", "tmpfs", Box::new(MemoryFs::new("guest"))), Err(VfsError::Busy));
+        assert_eq!(vfs.mount("/tmp/a", "tmpfs", "tmpfs", Box::new(MemoryFs::new("guest"))), Err(VfsError::NotADirectory));
+        assert_eq!(vfs.remove("/tmp", true), Err(VfsError::Busy));
+        assert_eq!(vfs.rename("/tmp/sub", "/tmp/other"), Err(VfsError::Busy));
+        assert_eq!(vfs.unmount("/tmp"), Err(VfsError::Busy));
+        assert_eq!(vfs.unmount("/"), Err(VfsError::Busy));
+
+        vfs.unmount("/tmp/sub").unwrap();
+        assert_eq!(vfs.read("/tmp/sub/b"), Err(VfsError::NotFound));
+        assert_eq!(vfs.unmount("/tmp/sub"), Err(VfsError::InvalidArgument));
+    }
+
+    #[test]
+    fn copy_and_move_between_mounts() {
+        let mut vfs = Vfs::new("guest");
+        vfs.create_dir_all("/tmp/a/b").unwrap();
+        vfs.write("/tmp/a/b/f", b"x", false).unwrap();
+        assert_eq!(vfs.copy("/tmp/a", "/home/guest/a", false), Err(VfsError::IsADirectory));
+        vfs.copy("/tmp/a", "/home/guest/a", true).unwrap();
+        assert_eq!(vfs.read("/home/guest/a/b/f").unwrap(), b"x");
+        assert_eq!(vfs.copy("/tmp/a", "/home/guest/a", true), Err(VfsError::IsADirectory));
+
+        vfs.rename("/tmp/a", "/c").unwrap();
+        assert!(!vfs.is_dir("/tmp/a"));
+        assert_eq!(vfs.read("/c/b/f").unwrap(), b"x");
+    }
+
+    #[test]
+    fn mount_points_list_the_mounted_root() {
+        let mut vfs = Vfs::new("guest");
+        vfs.mount("/mnt/remote", "test", "tmpfs", Box::new(MemoryFs::new("remote"))).unwrap();
+        let (name, meta) = vfs.read_dir("/mnt").unwrap().remove(0);
+        assert_eq!((name.as_str(), meta.owner.as_str()), ("remote", "remote"));
+    }
+
+    #[test]
+    fn times_are_formatted_in_utc() {
+        assert_eq!(format_time(0.0), "Jan  1 00:00");
+        assert_eq!(format_time(1_792_332_300_000.0), "Oct 18 14:05");
+    }
+}
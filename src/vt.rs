@@ -0,0 +1,303 @@
+use crate::screen::Color;
+
+const MAX_PARAMS: usize = 32;
+// longest OSC string kept, in bytes, the same limit as xterm's
+const MAX_OSC: usize = 4096;
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum Action {
+    Print(char),
+    // C0 control characters such as '\n', '\r', '\t' and backspace
+    Execute(char),
+    Esc(char),
+    Csi {
+        private: Option<char>,
+        params: Vec<u16>,
+        intermediates: String,
+        action: char,
+    },
+    Osc(Vec<String>),
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum State {
+    Ground,
+    Escape,
+    // ESC followed by an intermediate, e.g. the `(` in `ESC ( B`
+    EscapeIntermediate,
+    Csi,
+    Osc,
+    // ESC seen inside an OSC string, expecting the `\` of ST
+    OscEscape,
+}
+
+// A VT100/xterm escape sequence parser. Characters go in one at a time and
+// complete actions come out, so sequences may be split across writes.
+pub struct Parser {
+    state: State,
+    private: Option<char>,
+    params: Vec<u16>,
+    intermediates: String,
+    osc: String,
+    // the OSC string outgrew MAX_OSC, so it is dropped when it ends
+    osc_overflow: bool,
+}
+
+impl Default for Parser {
+    fn default() -> Self {
+        Parser::new()
+    }
+}
+
+impl Parser {
+    pub fn new() -> Parser {
+        Parser {
+            state: State::Ground,
+            private: None,
+            params: Vec::new(),
+            intermediates: String::new(),
+            osc: String::new(),
+            osc_overflow: false,
+        }
+    }
+
+    pub fn is_ground(&self) -> bool {
+        self.state == State::Ground
+    }
+
+    pub fn advance(&mut self, ch: char) -> Option<Action> {
+        match self.state {
+            State::Ground => match ch {
+                '\x1b' => {
+                    self.state = State::Escape;
+                    None
+                }
+                c if is_control(c) => Some(Action::Execute(c)),
+                c => Some(Action::Print(c)),
+            },
+            State::Escape => match ch {
+                '[' => {
+                    self.private = None;
+                    self.params.clear();
+                    self.intermediates.clear();
+                    self.state = State::Csi;
+                    None
+                }
+                ']' => {
+                    self.osc.clear();
+                    self.state = State::Osc;
+                    None
+                }
+                '\x1b' => None,
+                c if is_control(c) => Some(Action::Execute(c)),
+                ' '..='/' => {
+                    self.state = State::EscapeIntermediate;
+                    None
+                }
+                c => {
+                    self.state = State::Ground;
+                    Some(Action::Esc(c))
+                }
+            },
+            State::EscapeIntermediate => {
+                // character set designations are accepted and ignored
+                if !(' '..='/').contains(&ch) {
+                    self.state = State::Ground;
+                }
+                None
+            }
+            State::Csi => self.advance_csi(ch),
+            State::Osc => match ch {
+                '\x07' => self.dispatch_osc(),
+                '\x1b' => {
+                    self.state = State::OscEscape;
+                    None
+                }
+                c => {
+                    if self.osc.len() + c.len_utf8() > MAX_OSC {
+                        self.osc_overflow = true;
+                        self.osc.clear();
+                    } else if !self.osc_overflow {
+                        self.osc.push(c);
+                    }
+                    None
+                }
+            },
+            State::OscEscape => {
+                let action = self.dispatch_osc();
+                if ch != '\\' {
+                    // not a proper ST: the unfinished OSC is dropped and the
+                    // ESC starts a new sequence instead
+                    self.state = State::Escape;
+                    return self.advance(ch);
+                }
+                action
+            }
+        }
+    }
+
+    fn advance_csi(&mut self, ch: char) -> Option<Action> {
+        match ch {
+            '0'..='9' => {
+                if self.params.is_empty() {
+                    self.params.push(0);
+                }
+                let last = self.params.last_mut().unwrap();
+                *last = last.saturating_mul(10).saturating_add(ch as u16 - '0' as u16);
+                None
+            }
+            ';' | ':' => {
+                if self.params.is_empty() {
+                    self.params.push(0);
+                }
+                if self.params.len() < MAX_PARAMS {
+                    self.params.push(0);
+                }
+                None
+            }
+            '<'..='?' if self.params.is_empty() && self.private.is_none() => {
+                self.private = Some(ch);
+                None
+            }
+            ' '..='/' => {
+                self.intermediates.push(ch);
+                None
+            }
+            '@'..='~' => {
+                self.state = State::Ground;
+                Some(Action::Csi {
+                    private: self.private.take(),
+                    params: std::mem::take(&mut self.params),
+                    intermediates: std::mem::take(&mut self.intermediates),
+                    action: ch,
+                })
+            }
+            '\x1b' => {
+                self.state = State::Escape;
+                None
+            }
+            c if is_control(c) => Some(Action::Execute(c)),
+            _ => None,
+        }
+    }
+
+    fn dispatch_osc(&mut self) -> Option<Action> {
+        self.state = State::Ground;
+        let osc = std::mem::take(&mut self.osc);
+        let overflowed = std::mem::take(&mut self.osc_overflow);
+        (!overflowed).then(|| Action::Osc(osc.split(';').map(str::to_string).collect()))
+    }
+}
+
+fn is_control(ch: char) -> bool {
+    (ch as u32) < 0x20 || ch == '\x7f'
+}
+
+// the xterm 256 color palette
+pub fn palette(index: u8) -> Color {
+    const BASE: [Color; 16] = [
+        Color(0x00, 0x00, 0x00),
+        Color(0xCD, 0x00, 0x00),
+        Color(0x00, 0xCD, 0x00),
+        Color(0xCD, 0xCD, 0x00),
+        Color(0x00, 0x00, 0xEE),
+        Color(0xCD, 0x00, 0xCD),
+        Color(0x00, 0xCD, 0xCD),
+        Color(0xE5, 0xE5, 0xE5),
+        Color(0x7F, 0x7F, 0x7F),
+        Color(0xFF, 0x00, 0x00),
+        Color(0x00, 0xFF, 0x00),
+        Color(0xFF, 0xFF, 0x00),
+        Color(0x5C, 0x5C, 0xFF),
+        Color(0xFF, 0x00, 0xFF),
+        Color(0x00, 0xFF, 0xFF),
+        Color(0xFF, 0xFF, 0xFF),
+    ];
+    match index {
+        0..=15 => BASE[index as usize],
+        16..=231 => {
+            let level = |n: u8| if n == 0 { 0 } else { 55 + n * 40 };
+            let n = index - 16;
+            Color(level(n / 36), level((n / 6) % 6), level(n % 6))
+        }
+        _ => {
+            let gray = 8 + (index - 232) * 10;
+            Color(gray, gray, gray)
+        }
+    }
+}
+
+// Reads the color after an SGR 38 or 48, either `5;n` or `2;r;g;b`.
+// Returns the color and how many parameters it used.
+pub fn extended_color(params: &[u16]) -> (Option<Color>, usize) {
+    match params.first() {
+        Some(5) => match params.get(1) {
+            Some(&n) => (Some(palette(n.min(255) as u8)), 2),
+            None => (None, params.len()),
+        },
+        Some(2) if params.len() >= 4 => {
+            let channel = |i: usize| params[i].min(255) as u8;
+            (Some(Color(channel(1), channel(2), channel(3))), 4)
+        }
+        _ => (None, params.len()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(input: &str) -> Vec<Action> {
+        let mut parser = Parser::new();
+        input.chars().filter_map(|ch| parser.advance(ch)).collect()
+    }
+
+    #[test]
+    fn csi_with_params() {
+        assert_eq!(
+            parse("a\x1b[1;31mb"),
+            vec![
+                Action::Print('a'),
+                Action::Csi { private: None, params: vec![1, 31], intermediates: String::new(), action: 'm' },
+                Action::Print('b'),
+            ]
+        );
+    }
+
+    #[test]
+    fn private_marker_and_defaults() {
+        assert_eq!(
+            parse("\x1b[?25l\x1b[H"),
+            vec![
+                Action::Csi { private: Some('?'), params: vec![25], intermediates: String::new(), action: 'l' },
+                Action::Csi { private: None, params: vec![], intermediates: String::new(), action: 'H' },
+            ]
+        );
+    }
+
+    #[test]
+    fn osc_terminated_by_bel_or_st() {
+        let title = Action::Osc(vec!["0".to_string(), "hi".to_string()]);
+        assert_eq!(parse("\x1b]0;hi\x07"), vec![title.clone()]);
+        assert_eq!(parse("\x1b]0;hi\x1b\\x"), vec![title, Action::Print('x')]);
+        assert_eq!(parse("\x1b]0;hi\x1bc"), vec![Action::Esc('c')]);
+        assert_eq!(parse("\x1b]0;hi\x1b[31mx"), vec![
+            Action::Csi { private: None, params: vec![31], intermediates: String::new(), action: 'm' },
+            Action::Print('x'),
+        ]);
+        let long = format!("\x1b]0;{}\x07x", "a".repeat(MAX_OSC));
+        assert_eq!(parse(&long), vec![Action::Print('x')]);
+        let mut parser = Parser::new();
+        for ch in format!("\x1b]0;{}", "a".repeat(MAX_OSC * 4)).chars() {
+            parser.advance(ch);
+        }
+        assert!(parser.osc.len() <= MAX_OSC);
+    }
+
+    #[test]
+    fn palette_cube_and_grays() {
+        assert_eq!(palette(196), Color(0xFF, 0, 0));
+        assert_eq!(palette(232), Color(8, 8, 8));
+        assert_eq!(extended_color(&[2, 1, 2, 3]), (Some(Color(1, 2, 3)), 4));
+    }
+}